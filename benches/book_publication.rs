@@ -0,0 +1,101 @@
+//! Writer latency of book publication under heavy read load.
+//!
+//! `cargo bench --features bench --bench book_publication`
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use market_view::bench;
+use market_view::{Book, Order, SideKind};
+use rust_decimal::Decimal;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+const LEVELS: i64 = 1000;
+
+fn order(price: i64, size: i64) -> Order {
+    Order { price: Decimal::new(price, 2), size: Decimal::from(size) }
+}
+
+fn filled_book() -> Book {
+    let mut book = bench::book(LEVELS as usize, SideKind::Vec);
+    let bids = Vec::from_iter((0..LEVELS).map(|i| order(100_000 - i, 1)));
+    let asks = Vec::from_iter((0..LEVELS).map(|i| order(100_001 + i, 1)));
+    bench::apply(&mut book, &bids, &asks);
+    book
+}
+
+/// Depth event touching a few levels near the top of the book.
+fn event(i: i64) -> (Vec<Order>, Vec<Order>) {
+    (
+        Vec::from_iter((0..5).map(|j| order(100_000 - (i + j) % 20, (i + j) % 3))),
+        Vec::from_iter((0..5).map(|j| order(100_001 + (i + j) % 20, (i + j) % 3))),
+    )
+}
+
+fn with_readers(readers: usize, read: impl Fn() + Send + Sync + 'static, bench: impl FnOnce()) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let read = Arc::new(read);
+    let handles = Vec::from_iter((0..readers).map(|_| {
+        let stop = Arc::clone(&stop);
+        let read = Arc::clone(&read);
+
+        std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                read();
+            }
+        })
+    }));
+
+    bench();
+
+    stop.store(true, Ordering::Relaxed);
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+fn book_publication(c: &mut Criterion) {
+    let mut group = c.benchmark_group("book_publication");
+
+    for readers in [0, 2, 4] {
+        let book = Arc::new(Mutex::new(filled_book()));
+        with_readers(
+            readers,
+            {
+                let book = Arc::clone(&book);
+                move || { black_box(book.lock().unwrap().clone()); }
+            },
+            || {
+                let mut i = 0;
+                group.bench_function(BenchmarkId::new("mutex", readers), |b| b.iter(|| {
+                    let (bids, asks) = event(i);
+                    i += 1;
+                    bench::apply(&mut book.lock().unwrap(), &bids, &asks);
+                }));
+            },
+        );
+
+        let shared = bench::shared_book(filled_book());
+        let mut writer = bench::Writer::new(&shared);
+        with_readers(
+            readers,
+            {
+                let shared = shared.clone();
+                move || { black_box(shared.load()); }
+            },
+            || {
+                let mut i = 0;
+                group.bench_function(BenchmarkId::new("arc_swap", readers), |b| b.iter(|| {
+                    let (bids, asks) = event(i);
+                    i += 1;
+                    writer.apply(bids, asks);
+                }));
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, book_publication);
+criterion_main!(benches);
@@ -0,0 +1,89 @@
+//! Depth messages decoded per second on a single core.
+//!
+//! Decodes messages recorded from `wss://data-stream.binance.vision/stream`
+//! (one raw message per line), `benches/data/depth.jsonl` unless `MARKET_VIEW_RECORDING` points to others.
+//! Without a recording decodes synthetic ones.
+//!
+//! `cargo bench --features bench --bench decode` \
+//! `cargo bench --features bench,simd-json --bench decode`
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use market_view::bench;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::hint::black_box;
+
+/// Previous owned representation, kept as a baseline.
+#[derive(Deserialize)]
+struct OwnedEvent {
+    data: OwnedEventPayload,
+}
+
+#[derive(Deserialize)]
+#[allow(non_snake_case, dead_code)]
+struct OwnedEventPayload {
+    E: u64,
+    s: String,
+    U: u64,
+    u: u64,
+    b: Vec<(Decimal, Decimal)>,
+    a: Vec<(Decimal, Decimal)>,
+}
+
+/// Recorded by `cargo run --example record_depth`.
+const RECORDING: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/data/depth.jsonl");
+
+fn recording() -> Option<String> {
+    match std::env::var("MARKET_VIEW_RECORDING") {
+        Ok(path) => Some(std::fs::read_to_string(path).unwrap()),
+        Err(_) => std::fs::read_to_string(RECORDING).ok(),
+    }
+}
+
+fn messages() -> Vec<Vec<u8>> {
+    if let Some(recording) = recording() {
+        return recording
+            .lines()
+            .map(|line| line.as_bytes().to_vec())
+            .collect();
+    }
+
+    Vec::from_iter((0..1000_u64).map(|i| {
+        let levels = |base: u64| (0..1 + i % 20)
+            .map(|j| format!(r#"["{}.{:02}000000","{}.{:08}"]"#, base + j, i % 100, i % 7, i * 7919 % 100_000_000))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        format!(
+            r#"{{"stream":"btcusdt@depth@100ms","data":{{"e":"depthUpdate","E":{},"s":"BTCUSDT","U":{},"u":{},"b":[{}],"a":[{}]}}}}"#,
+            1737000000000 + i * 100, i * 10, i * 10 + 9, levels(104000), levels(104100),
+        ).into_bytes()
+    }))
+}
+
+fn decode(c: &mut Criterion) {
+    let messages = messages();
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(messages.len() as u64));
+
+    group.bench_function("owned", |b| b.iter(|| {
+        for msg in &messages {
+            black_box(serde_json::from_slice::<OwnedEvent>(msg).unwrap().data);
+        }
+    }));
+
+    group.bench_function("borrowed", |b| b.iter_batched(
+        || messages.clone(),
+        |mut messages| {
+            for msg in &mut messages {
+                black_box(bench::decode_binance_spot_depth(msg));
+            }
+        },
+        BatchSize::LargeInput,
+    ));
+
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
@@ -0,0 +1,100 @@
+//! Per-update cost of book sides at different depths.
+//!
+//! Replays a depth stream recorded from `wss://data-stream.binance.vision/stream`
+//! (one raw message per line), `benches/data/depth.jsonl` unless `MARKET_VIEW_RECORDING` points to another one.
+//! Without a recording replays a synthetic stream concentrated near the top of the book.
+//!
+//! `cargo bench --features bench --bench side`
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use market_view::bench;
+use market_view::{Order, SideKind};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+type Event = (Vec<Order>, Vec<Order>);
+
+/// Recorded by `cargo run --example record_depth`.
+const RECORDING: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/data/depth.jsonl");
+
+fn recording() -> Option<String> {
+    match std::env::var("MARKET_VIEW_RECORDING") {
+        Ok(path) => Some(std::fs::read_to_string(path).unwrap()),
+        Err(_) => std::fs::read_to_string(RECORDING).ok(),
+    }
+}
+
+fn recorded_stream(recording: &str) -> Vec<Event> {
+    let parse = |levels: &serde_json::Value| Vec::from_iter(
+        levels.as_array().unwrap().iter().map(|level| Order {
+            price: Decimal::from_str(level[0].as_str().unwrap()).unwrap(),
+            size: Decimal::from_str(level[1].as_str().unwrap()).unwrap(),
+        })
+    );
+
+    recording
+        .lines()
+        .map(|line| {
+            let msg = serde_json::from_str::<serde_json::Value>(line).unwrap();
+            (parse(&msg["data"]["b"]), parse(&msg["data"]["a"]))
+        })
+        .collect()
+}
+
+fn synthetic_stream(depth: i64) -> Vec<Event> {
+    // Linear congruential generator keeps the stream reproducible.
+    let mut state = 42_u64;
+    let mut next = |n: i64| {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 33) as i64 % n
+    };
+
+    Vec::from_iter((0..10_000).map(|_| {
+        let mut side = |base: i64, dir: i64| Vec::from_iter((0..10).map(|_| {
+            // Most updates hit the top levels, some go deep.
+            let distance = if next(10) < 8 { next(20) } else { next(depth * 2) };
+            let size = if next(5) == 0 { 0 } else { 1 + next(100) };
+            Order { price: Decimal::new(base + dir * distance, 2), size: Decimal::from(size) }
+        }));
+        (side(100_000, -1), side(100_001, 1))
+    }))
+}
+
+fn side(c: &mut Criterion) {
+    let mut group = c.benchmark_group("side");
+
+    let recording = recording();
+
+    for depth in [100, 1000, 5000] {
+        let stream = match &recording {
+            Some(recording) => recorded_stream(recording),
+            None => synthetic_stream(depth),
+        };
+        let updates = stream.iter().map(|(bids, asks)| bids.len() + asks.len()).sum::<usize>();
+        group.throughput(Throughput::Elements(updates as u64));
+
+        for kind in [SideKind::Vec, SideKind::BTree] {
+            let mut book = bench::book(depth as usize, kind);
+            let bids = Vec::from_iter((0..depth).map(|i| Order {
+                price: Decimal::new(100_000 - i, 2),
+                size: Decimal::ONE,
+            }));
+            let asks = Vec::from_iter((0..depth).map(|i| Order {
+                price: Decimal::new(100_001 + i, 2),
+                size: Decimal::ONE,
+            }));
+            bench::apply(&mut book, &bids, &asks);
+
+            group.bench_function(BenchmarkId::new(format!("{kind:?}"), depth), |b| b.iter(|| {
+                for (bids, asks) in &stream {
+                    bench::apply(&mut book, bids, asks);
+                }
+            }));
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, side);
+criterion_main!(benches);
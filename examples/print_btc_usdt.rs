@@ -1,18 +1,18 @@
 use market_view::{Book, Exchange, Pair, Place, Platform};
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
 
 const PRINT_INTERVAL: Duration = Duration::from_secs(1);
 
-fn print_best_orders(books: HashMap<&Place, HashMap<&Pair, Book>>) {
+fn print_best_orders(books: HashMap<&Place, HashMap<&Pair, Arc<Book>>>) {
     let place = (Exchange::Binance, Platform::Spot);
     let pair = Pair::new(String::from("btc"), String::from("usdt"));
     let book = &books[&place][&pair];
 
-    if book.bids().is_empty() || book.asks().is_empty() {
-        log::info!("Starting, please wait...");
-    } else {
-        log::info!("[{pair}] best bid: {:?} best ask: {:?}", book.bids()[0], book.asks()[0]);
+    match (book.bids().first(), book.asks().first()) {
+        (Some(bid), Some(ask)) => log::info!("[{pair}] best bid: {bid:?} best ask: {ask:?}"),
+        _ => log::info!("Starting, please wait..."),
     }
 }
 
@@ -20,17 +20,19 @@ fn print_best_orders(books: HashMap<&Place, HashMap<&Pair, Book>>) {
 async fn main() {
     log::info!("You can check it here: https://www.binance.com/en/trade/BTC_USDT?type=spot");
 
-    let books = market_view::start(vec![
+    let places = market_view::start(vec![
         market_view::Config::new(
             (Exchange::Binance, Platform::Spot),
             100,
             vec![Pair::new(String::from("btc"), String::from("usdt"))],
-            // exchanges::binance::spot::get_pairs().await.unwrap(),
+            // market_view::exchanges::binance::spot::get_pairs(
+            //     "[binance] [spot]", Arc::new(market_view::rate_limit::LocalBackend::new()),
+            // ).await.unwrap(),
         )
     ]);
 
     loop {
-        print_best_orders(market_view::copy_books(&books));
+        print_best_orders(market_view::copy_books(&places));
         tokio::time::sleep(PRINT_INTERVAL).await;
     }
 }
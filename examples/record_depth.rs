@@ -0,0 +1,32 @@
+//! Records raw depth messages for benchmarks, one message per line.
+//!
+//! `cargo run --example record_depth -- [path] [messages]`,
+//! by default 600 messages of `btcusdt@depth@100ms` to `benches/data/depth.jsonl`.
+
+use futures::StreamExt;
+use std::io::Write;
+
+const ENDPOINT: &str = "wss://data-stream.binance.vision/stream?streams=btcusdt@depth@100ms";
+
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap_or_else(|| String::from("benches/data/depth.jsonl"));
+    let messages = args.next().map_or(600, |n| n.parse::<usize>().unwrap());
+
+    let uri = http::Uri::from_static(ENDPOINT);
+    let (mut client, _) = tokio_websockets::ClientBuilder::from_uri(uri).connect().await.unwrap();
+    let mut file = std::io::BufWriter::new(std::fs::File::create(&path).unwrap());
+
+    let mut recorded = 0;
+    while recorded < messages {
+        let msg = client.next().await.unwrap().unwrap();
+        if let Some(text) = msg.as_text() {
+            writeln!(file, "{text}").unwrap();
+            recorded += 1;
+        }
+    }
+
+    file.flush().unwrap();
+    log::info!("Recorded {recorded} messages to {path}");
+}
@@ -0,0 +1,43 @@
+//! Internals exposed to benchmarks.
+
+use crate::{Book, BookWriter, Order, SharedBook, SideKind};
+
+pub fn book(cap: usize, kind: SideKind) -> Book {
+    Book::new(cap, kind)
+}
+
+pub fn apply(book: &mut Book, bids: &[Order], asks: &[Order]) {
+    for &order in bids {
+        book.bids.diff_update(order);
+    }
+    for &order in asks {
+        book.asks.diff_update(order);
+    }
+}
+
+pub struct Writer(BookWriter);
+
+impl Writer {
+    pub fn new(shared: &SharedBook) -> Self {
+        Self(shared.writer())
+    }
+
+    pub fn apply(&mut self, bids: Vec<Order>, asks: Vec<Order>) {
+        self.0.update(move |book| apply(book, &bids, &asks));
+    }
+}
+
+pub fn shared_book(book: Book) -> SharedBook {
+    SharedBook::new(book)
+}
+
+/// Decodes Binance spot depth message into the symbol, last update id and levels.
+pub fn decode_binance_spot_depth(body: &mut [u8]) -> (&str, u64, Vec<Order>, Vec<Order>) {
+    use crate::exchanges::binance::spot::decode::{decode_message, StreamEvent};
+
+    let (symbol, StreamEvent::Depth(event)) = decode_message(body).unwrap() else {
+        panic!("not a depth message");
+    };
+
+    (symbol, event.u, event.b, event.a)
+}
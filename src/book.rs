@@ -1,4 +1,5 @@
 use rust_decimal::Decimal;
+use std::collections::{btree_map, BTreeMap};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Order {
@@ -6,92 +7,400 @@ pub struct Order {
     pub size: Decimal,
 }
 
+/// Best bid and offer as of the update id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bbo {
+    pub update_id: u64,
+    pub bid: Order,
+    pub ask: Order,
+}
+
+/// Data structure which keeps levels of a book side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SideKind {
+    /// Sorted vector, fastest for shallow books.
+    #[default]
+    Vec,
+    /// B-tree, inserts and removes deep in the book don't shift other levels.
+    BTree,
+}
+
+#[derive(Debug, Clone)]
+enum Levels {
+    Vec(Vec<Order>),
+    BTree(BTreeMap<Decimal, Decimal>),
+}
+
+/// Levels of a book side from best to worst.
+///
+/// Levels beyond capacity aren't tracked, so once the side drops some of them
+/// or is set from a snapshot of limited depth, deeper levels may be missing.
+/// Levels up to the worst known price are complete, see [`Side::valid_len`].
 #[derive(Debug, Clone)]
 pub struct Side<const REV: bool> {
-    vec: Vec<Order>,
+    levels: Levels,
     cap: usize,
+    // Worst price up to which levels are complete, `None` if all of them are.
+    known: Option<Decimal>,
+}
+
+/// Broken invariant of a [`Book`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Violation {
+    /// Price at `idx` doesn't follow the previous level strictly.
+    Unsorted { bid: bool, idx: usize },
+    NonPositiveSize { bid: bool, price: Decimal },
+    OverCapacity { bid: bool, len: usize },
+    Crossed { bid: Decimal, ask: Decimal },
+}
+
+impl Violation {
+    /// Name for metric labels.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Unsorted { .. } => "unsorted",
+            Self::NonPositiveSize { .. } => "non_positive_size",
+            Self::OverCapacity { .. } => "over_capacity",
+            Self::Crossed { .. } => "crossed",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Book {
     pub(crate) bids: Side<true>,
     pub(crate) asks: Side<false>,
+    // Last update applied, zero until the first snapshot.
+    pub(crate) update_id: u64,
+}
+
+pub struct Iter<'a>(IterInner<'a>);
+
+enum IterInner<'a> {
+    Vec(std::slice::Iter<'a, Order>),
+    BTree(btree_map::Iter<'a, Decimal, Decimal>),
+    BTreeRev(std::iter::Rev<btree_map::Iter<'a, Decimal, Decimal>>),
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Order;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.0 {
+            IterInner::Vec(iter) => iter.next().copied(),
+            IterInner::BTree(iter) => iter.next().map(|(&price, &size)| Order { price, size }),
+            IterInner::BTreeRev(iter) => iter.next().map(|(&price, &size)| Order { price, size }),
+        }
+    }
 }
 
 impl<const REV: bool> Side<REV> {
-    fn new(cap: usize) -> Self {
-        Self {
-            vec: Vec::with_capacity(cap),
-            cap,
+    fn new(cap: usize, kind: SideKind) -> Self {
+        let levels = match kind {
+            SideKind::Vec => Levels::Vec(Vec::with_capacity(cap)),
+            SideKind::BTree => Levels::BTree(BTreeMap::new()),
+        };
+
+        Self { levels, cap, known: None }
+    }
+
+    /// Whether price `a` is as good as `b` or better.
+    fn as_good(a: Decimal, b: Decimal) -> bool {
+        if REV { a >= b } else { a <= b }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.levels {
+            Levels::Vec(vec) => vec.len(),
+            Levels::BTree(map) => map.len(),
         }
     }
 
-    pub(crate) fn shot_update(&mut self, orders: Vec<Order>) {
-        self.vec = orders;
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Best level.
+    pub fn first(&self) -> Option<Order> {
+        match &self.levels {
+            Levels::Vec(vec) => vec.first().copied(),
+            Levels::BTree(map) => if REV { map.last_key_value() } else { map.first_key_value() }
+                .map(|(&price, &size)| Order { price, size }),
+        }
+    }
+
+    /// Level at `idx` counting from the best one.
+    pub fn get(&self, idx: usize) -> Option<Order> {
+        match &self.levels {
+            Levels::Vec(vec) => vec.get(idx).copied(),
+            Levels::BTree(_) => self.iter().nth(idx),
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter(match &self.levels {
+            Levels::Vec(vec) => IterInner::Vec(vec.iter()),
+            Levels::BTree(map) => if REV {
+                IterInner::BTreeRev(map.iter().rev())
+            } else {
+                IterInner::BTree(map.iter())
+            },
+        })
+    }
+
+    pub fn to_vec(&self) -> Vec<Order> {
+        self.iter().collect()
+    }
+
+    /// Levels from the best one, `None` for [`SideKind::BTree`] sides, which don't keep them contiguous.
+    pub fn as_slice(&self) -> Option<&[Order]> {
+        match &self.levels {
+            Levels::Vec(vec) => Some(vec),
+            Levels::BTree(_) => None,
+        }
+    }
+
+    /// Number of best levels which are known to be complete.
+    pub fn valid_len(&self) -> usize {
+        let Some(known) = self.known else {
+            return self.len();
+        };
+
+        match &self.levels {
+            Levels::Vec(vec) => vec.partition_point(|o| Self::as_good(o.price, known)),
+            Levels::BTree(map) => if REV { map.range(known..).count() } else { map.range(..=known).count() },
+        }
+    }
+
+    /// Worst price up to which levels are complete, `None` if all of them are.
+    pub(crate) fn known_worst(&self) -> Option<Decimal> {
+        self.known
+    }
+
+    fn last(&self) -> Option<Order> {
+        match &self.levels {
+            Levels::Vec(vec) => vec.last().copied(),
+            Levels::BTree(map) => if REV { map.first_key_value() } else { map.last_key_value() }
+                .map(|(&price, &size)| Order { price, size }),
+        }
+    }
+
+    fn check(&self) -> Result<(), Violation> {
+        if self.len() > self.cap {
+            return Err(Violation::OverCapacity { bid: REV, len: self.len() });
+        }
+        Self::check_levels(0, self.iter())
+    }
+
+    /// Checks levels at `prices` and next to them, the only ones an update of these prices
+    /// may break in a valid side.
+    fn check_near(&self, prices: &[Decimal]) -> Result<(), Violation> {
+        if self.len() > self.cap {
+            return Err(Violation::OverCapacity { bid: REV, len: self.len() });
+        }
+
+        match &self.levels {
+            Levels::Vec(vec) => {
+                for &price in prices {
+                    let (Ok(idx) | Err(idx)) = Self::search(vec, Order { price, size: Decimal::ZERO });
+                    let start = idx.saturating_sub(1);
+                    Self::check_levels(start, vec[start..(idx + 2).min(vec.len())].iter().copied())?;
+                }
+            }
+            // Keys are always sorted, only sizes may be broken.
+            Levels::BTree(map) => {
+                for price in prices {
+                    if let Some(&size) = map.get(price) {
+                        Self::check_levels(0, std::iter::once(Order { price: *price, size }))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that `levels` starting at index `start` have positive sizes and follow each other strictly.
+    fn check_levels(start: usize, levels: impl Iterator<Item = Order>) -> Result<(), Violation> {
+        let mut prev = None;
+
+        for (idx, order) in (start..).zip(levels) {
+            if order.size <= Decimal::ZERO {
+                return Err(Violation::NonPositiveSize { bid: REV, price: order.price });
+            }
+            let sorted = prev.is_none_or(|prev| if REV { order.price < prev } else { order.price > prev });
+            if !sorted {
+                return Err(Violation::Unsorted { bid: REV, idx });
+            }
+            prev = Some(order.price);
+        }
+        Ok(())
+    }
+
+    /// Sets levels from a snapshot of `limit` levels, which is cut off unless shorter.
+    pub(crate) fn shot_update(&mut self, orders: &[Order], limit: usize) {
+        self.known = if orders.len() < limit { None } else { orders.last().map(|o| o.price) };
+
+        match &mut self.levels {
+            Levels::Vec(vec) => {
+                vec.clear();
+                vec.extend_from_slice(orders);
+            }
+            Levels::BTree(map) => {
+                map.clear();
+                map.extend(orders.iter().map(|o| (o.price, o.size)));
+            }
+        }
     }
 
     pub(crate) fn diff_update(&mut self, order: Order) {
+        let dropped = match &mut self.levels {
+            Levels::Vec(vec) => Self::vec_diff_update(vec, self.cap, order),
+            Levels::BTree(map) => Self::btree_diff_update(map, self.cap, order),
+        };
+
+        if dropped.is_some_and(|dropped| self.known.is_none_or(|known| Self::as_good(dropped, known))) {
+            // Levels beyond the remaining worst one aren't complete anymore.
+            self.known = self.last().map(|o| o.price);
+        }
+    }
+
+    /// Returns price of the level dropped because of capacity.
+    fn vec_diff_update(vec: &mut Vec<Order>, cap: usize, order: Order) -> Option<Decimal> {
         if order.size == Decimal::ZERO {
             // Remove existing order.
-            if let Ok(idx) = self.search(order) {
+            if let Ok(idx) = Self::search(vec, order) {
                 // Found order with target price.
-                self.vec.remove(idx);
+                vec.remove(idx);
             } // It's ok if such order is not found.
         } else {
             // Insert new order or update existing.
-            match self.search(order) {
+            match Self::search(vec, order) {
                 Ok(idx) => {
                     // Update existing order.
-                    self.vec[idx].size = order.size;
+                    vec[idx].size = order.size;
                 }
                 Err(idx) => {
                     // Maybe insert new order.
-                    if idx < self.cap {
+                    if idx < cap {
                         // We don't want to exceed order book's capacity.
-                        if self.vec.len() == self.cap {
+                        let popped = if vec.len() == cap {
                             // If capacity is full, remove last (worst) element
                             // because otherwise it'll be shifted to the right
                             // increasing length beyond capacity.
-                            self.vec.pop();
-                        }
+                            vec.pop().map(|o| o.price)
+                        } else {
+                            None
+                        };
                         // Insert new order.
-                        self.vec.insert(idx, order);
+                        vec.insert(idx, order);
+                        return popped;
                     }
+                    return Some(order.price);
                 }
             }
         }
+        None
     }
 
-    fn search(&self, order: Order) -> Result<usize, usize> {
+    /// Returns price of the level dropped because of capacity.
+    fn btree_diff_update(map: &mut BTreeMap<Decimal, Decimal>, cap: usize, order: Order) -> Option<Decimal> {
+        if order.size == Decimal::ZERO {
+            // It's ok if such order is not found.
+            map.remove(&order.price);
+        } else if let Some(size) = map.get_mut(&order.price) {
+            *size = order.size;
+        } else {
+            let mut dropped = None;
+            if map.len() >= cap {
+                // Capacity is full, new order replaces the worst one if it's better.
+                let worst = if REV { map.first_key_value() } else { map.last_key_value() };
+                let Some((&worst, _)) = worst else { return Some(order.price) };
+
+                let better = if REV { order.price > worst } else { order.price < worst };
+                if !better {
+                    return Some(order.price);
+                }
+                map.remove(&worst);
+                dropped = Some(worst);
+            }
+            map.insert(order.price, order.size);
+            return dropped;
+        }
+        None
+    }
+
+    fn search(vec: &[Order], order: Order) -> Result<usize, usize> {
         if REV {
-            self.vec.binary_search_by(|&o| order.price.cmp(&o.price))
+            vec.binary_search_by(|&o| order.price.cmp(&o.price))
         } else {
-            self.vec.binary_search_by(|&o| o.price.cmp(&order.price))
+            vec.binary_search_by(|&o| o.price.cmp(&order.price))
         }
     }
 }
 
 impl Book {
-    pub(crate) fn new(cap: usize) -> Self {
+    pub(crate) fn new(cap: usize, kind: SideKind) -> Self {
         Self {
-            bids: Side::new(cap),
-            asks: Side::new(cap),
+            bids: Side::new(cap, kind),
+            asks: Side::new(cap, kind),
+            update_id: 0,
         }
     }
 
+    /// Last update id applied to the book.
+    pub fn update_id(&self) -> u64 {
+        self.update_id
+    }
+
+    /// Best levels of the book, `None` if a side is empty.
+    pub fn bbo(&self) -> Option<Bbo> {
+        Some(Bbo { update_id: self.update_id, bid: self.bids.first()?, ask: self.asks.first()? })
+    }
+
     pub fn capacity(&self) -> usize {
         self.bids.cap
     }
-    
-    pub fn bids(&self) -> &Vec<Order> {
-        &self.bids.vec
+
+    pub fn bids(&self) -> &Side<true> {
+        &self.bids
     }
-    
-    pub fn asks(&self) -> &Vec<Order> {
-        &self.asks.vec
+
+    pub fn asks(&self) -> &Side<false> {
+        &self.asks
+    }
+
+    /// Checks that levels are sorted from best to worst, have positive sizes, fit capacity
+    /// and that the best bid is below the best ask.
+    pub(crate) fn check(&self) -> Result<(), Violation> {
+        self.bids.check()?;
+        self.asks.check()?;
+        self.check_cross()
+    }
+
+    /// Same as [`Book::check`] for a valid book after an update of `bids` and `asks` prices,
+    /// but checks only the levels it may have broken.
+    pub(crate) fn check_near(&self, bids: &[Decimal], asks: &[Decimal]) -> Result<(), Violation> {
+        self.bids.check_near(bids)?;
+        self.asks.check_near(asks)?;
+        self.check_cross()
+    }
+
+    fn check_cross(&self) -> Result<(), Violation> {
+        match (self.bids.first(), self.asks.first()) {
+            (Some(bid), Some(ask)) if bid.price >= ask.price => {
+                Err(Violation::Crossed { bid: bid.price, ask: ask.price })
+            }
+            _ => Ok(()),
+        }
     }
 }
 
+/// Order of any numbers, for tests.
+#[cfg(test)]
+pub(crate) fn order(price: impl Into<Decimal>, size: impl Into<Decimal>) -> Order {
+    Order { price: price.into(), size: size.into() }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -99,20 +408,130 @@ mod tests {
 
     #[test]
     fn side() {
-        let mut side = Side::<false>::new(3);
-        
-        let order0_5 = Order { price: dec!(0.5), size: dec!(43.94) };
-        let order1 = Order { price: dec!(1.0), size: dec!(11.04) };
-        let order1_5 = Order { price: dec!(1.5), size: dec!(98.5) };
-        let order2 = Order { price: dec!(2.0), size: dec!(52.3) };
-        let order2_5 = Order { price: dec!(2.5), size: dec!(44.0) };
-        
-        side.diff_update(order2);
-        side.diff_update(order1);
-        side.diff_update(order0_5);
-        side.diff_update(order2_5);
-        side.diff_update(order1_5);
-
-        assert_eq!(side.vec, vec![order0_5, order1, order1_5]);
+        for kind in [SideKind::Vec, SideKind::BTree] {
+            let mut side = Side::<false>::new(3, kind);
+
+            let order0_5 = Order { price: dec!(0.5), size: dec!(43.94) };
+            let order1 = Order { price: dec!(1.0), size: dec!(11.04) };
+            let order1_5 = Order { price: dec!(1.5), size: dec!(98.5) };
+            let order2 = Order { price: dec!(2.0), size: dec!(52.3) };
+            let order2_5 = Order { price: dec!(2.5), size: dec!(44.0) };
+
+            side.diff_update(order2);
+            side.diff_update(order1);
+            side.diff_update(order0_5);
+            side.diff_update(order2_5);
+            side.diff_update(order1_5);
+
+            assert_eq!(side.to_vec(), vec![order0_5, order1, order1_5]);
+        }
+    }
+
+    #[test]
+    fn reversed_side() {
+        for kind in [SideKind::Vec, SideKind::BTree] {
+            let mut side = Side::<true>::new(2, kind);
+
+            let order1 = Order { price: dec!(1), size: dec!(5) };
+            let order2 = Order { price: dec!(2), size: dec!(6) };
+            let order3 = Order { price: dec!(3), size: dec!(7) };
+
+            side.diff_update(order1);
+            side.diff_update(order3);
+            side.diff_update(order2);
+            assert_eq!(side.to_vec(), vec![order3, order2]);
+            assert_eq!(side.first(), Some(order3));
+            assert_eq!(side.get(1), Some(order2));
+            assert_eq!(side.as_slice(), (kind == SideKind::Vec).then_some(&[order3, order2][..]));
+
+            side.diff_update(Order { price: dec!(3), size: dec!(0) });
+            side.diff_update(Order { price: dec!(2), size: dec!(8) });
+            assert_eq!(side.to_vec(), vec![Order { price: dec!(2), size: dec!(8) }]);
+        }
+    }
+
+    #[test]
+    fn check() {
+        let mut book = Book::new(3, SideKind::Vec);
+
+        book.bids.shot_update(&[order(dec!(2), dec!(1)), order(dec!(1), dec!(1))], 3);
+        book.asks.shot_update(&[order(dec!(3), dec!(1)), order(dec!(4), dec!(1))], 3);
+        assert_eq!(book.check(), Ok(()));
+
+        book.asks.shot_update(&[order(dec!(2), dec!(1))], 3);
+        assert_eq!(book.check(), Err(Violation::Crossed { bid: dec!(2), ask: dec!(2) }));
+
+        book.asks.shot_update(&[order(dec!(4), dec!(1)), order(dec!(3), dec!(1))], 3);
+        assert_eq!(book.check(), Err(Violation::Unsorted { bid: false, idx: 1 }));
+
+        book.asks.shot_update(&[order(dec!(3), dec!(0))], 3);
+        assert_eq!(book.check(), Err(Violation::NonPositiveSize { bid: false, price: dec!(3) }));
+
+        book.bids.shot_update(&[order(dec!(2), dec!(1)), order(dec!(2), dec!(1))], 3);
+        assert_eq!(book.check(), Err(Violation::Unsorted { bid: true, idx: 1 }));
+
+        book.bids.shot_update(&[order(dec!(2), dec!(1)), order(dec!(1), dec!(1)), order(dec!(0.5), dec!(1)), order(dec!(0.1), dec!(1))], 3);
+        assert_eq!(book.check(), Err(Violation::OverCapacity { bid: true, len: 4 }));
+    }
+
+    #[test]
+    fn check_near() {
+        for kind in [SideKind::Vec, SideKind::BTree] {
+            let mut book = Book::new(5, kind);
+            book.bids.shot_update(&[order(5, 1), order(4, 1), order(3, 1), order(2, 1)], 5);
+            book.asks.shot_update(&[order(6, 1)], 5);
+            assert_eq!(book.check_near(&[dec!(4)], &[]), Ok(()));
+
+            book.bids.diff_update(order(3, -1));
+            let violation = Violation::NonPositiveSize { bid: true, price: dec!(3) };
+            assert_eq!(book.check_near(&[dec!(3)], &[]), Err(violation));
+            // Levels far from the update aren't checked.
+            assert_eq!(book.check_near(&[dec!(5)], &[]), Ok(()));
+
+            book.bids.diff_update(order(3, 1));
+            book.bids.diff_update(order(7, 1));
+            assert_eq!(book.check_near(&[dec!(7)], &[]), Err(Violation::Crossed { bid: dec!(7), ask: dec!(6) }));
+        }
+
+        // Only vectors can get unsorted.
+        let mut book = Book::new(5, SideKind::Vec);
+        book.asks.shot_update(&[order(1, 1), order(2, 1), order(3, 1), order(3, 1)], 5);
+        assert_eq!(book.check_near(&[], &[dec!(3)]), Err(Violation::Unsorted { bid: false, idx: 3 }));
+        assert_eq!(book.check_near(&[], &[dec!(1)]), Ok(()));
+    }
+
+    #[test]
+    fn valid_len() {
+        for kind in [SideKind::Vec, SideKind::BTree] {
+            let mut side = Side::<true>::new(3, kind);
+
+            // Snapshot is cut off at 8.
+            side.shot_update(&[order(dec!(10), dec!(1)), order(dec!(9), dec!(1)), order(dec!(8), dec!(1))], 3);
+            assert_eq!(side.valid_len(), 3);
+
+            // Level 7 beyond the snapshot may be missing.
+            side.diff_update(order(dec!(10), dec!(0)));
+            side.diff_update(order(dec!(6), dec!(1)));
+            assert_eq!(side.to_vec(), vec![order(dec!(9), dec!(1)), order(dec!(8), dec!(1)), order(dec!(6), dec!(1))]);
+            assert_eq!(side.valid_len(), 2);
+
+            // New best level pushes out the worst one, which isn't known to be complete anyway.
+            side.diff_update(order(dec!(11), dec!(1)));
+            assert_eq!(side.valid_len(), 3);
+            assert_eq!(side.known_worst(), Some(dec!(8)));
+
+            // Dropping a known level moves the boundary.
+            side.diff_update(order(dec!(12), dec!(1)));
+            assert_eq!(side.known_worst(), Some(dec!(9)));
+            side.diff_update(order(dec!(12), dec!(0)));
+            side.diff_update(order(dec!(11), dec!(0)));
+            assert_eq!(side.valid_len(), 1);
+
+            // Snapshot shorter than its limit has every level.
+            side.shot_update(&[order(dec!(10), dec!(1))], 3);
+            side.diff_update(order(dec!(9), dec!(1)));
+            assert_eq!(side.known_worst(), None);
+            assert_eq!(side.valid_len(), 2);
+        }
     }
 }
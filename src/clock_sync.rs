@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Samples the estimate is chosen from.
+const SAMPLES: usize = 8;
+/// Precision of exchange times, which are in milliseconds.
+const SERVER_RESOLUTION: Duration = Duration::from_millis(1);
+
+/// Estimated offset of exchange clock relative to local one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockOffset {
+    /// Exchange time minus local time, in nanoseconds.
+    pub offset_nanos: i64,
+    /// True offset is within `offset_nanos ± uncertainty`.
+    pub uncertainty: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    offset_nanos: i64,
+    rtt: Duration,
+}
+
+/// NTP-style estimate of exchange clock offset from server time requests.
+///
+/// Every sample assumes the server read its clock halfway through the round trip,
+/// which is off by at most half of it. The estimate is taken from the sample
+/// with the smallest round trip among recent ones, as it's the least affected by delays.
+#[derive(Debug)]
+pub(crate) struct ClockSync {
+    samples: Mutex<VecDeque<Sample>>,
+    offset_nanos: AtomicI64,
+    // Zero until the first sample.
+    uncertainty_nanos: AtomicU64,
+}
+
+fn nanos(time: SystemTime) -> i128 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_nanos() as i128,
+        Err(err) => -(err.duration().as_nanos() as i128),
+    }
+}
+
+impl ClockSync {
+    pub(crate) fn new() -> Self {
+        Self {
+            samples: Mutex::default(),
+            offset_nanos: AtomicI64::new(0),
+            uncertainty_nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// Adds a sample of server time read during the round trip `rtt` of a request sent at local `sent`.
+    pub(crate) fn add_sample(&self, sent: SystemTime, rtt: Duration, server: SystemTime) {
+        let midpoint = nanos(sent) + rtt.as_nanos() as i128 / 2;
+        // Server truncates its time, so it's read half of the resolution later on average.
+        let server = nanos(server) + SERVER_RESOLUTION.as_nanos() as i128 / 2;
+        // Server time is off by up to half of the round trip and half of the resolution.
+        let sample = Sample { offset_nanos: (server - midpoint) as i64, rtt: rtt + SERVER_RESOLUTION };
+
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() == SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+
+        let best = samples.iter().min_by_key(|s| s.rtt).unwrap();
+        self.offset_nanos.store(best.offset_nanos, Ordering::Relaxed);
+        self.uncertainty_nanos.store((best.rtt / 2).as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// `None` until the first sample.
+    pub(crate) fn offset(&self) -> Option<ClockOffset> {
+        let uncertainty = self.uncertainty_nanos.load(Ordering::Relaxed);
+
+        (uncertainty > 0).then(|| ClockOffset {
+            offset_nanos: self.offset_nanos.load(Ordering::Relaxed),
+            uncertainty: Duration::from_nanos(uncertainty),
+        })
+    }
+
+    /// Converts local time to exchange time, unchanged until the first sample.
+    pub(crate) fn to_exchange(&self, local: SystemTime) -> SystemTime {
+        let offset = self.offset_nanos.load(Ordering::Relaxed);
+
+        if offset >= 0 {
+            local + Duration::from_nanos(offset as u64)
+        } else {
+            local - Duration::from_nanos(offset.unsigned_abs())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset() {
+        let clock = ClockSync::new();
+        let local = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let ms = Duration::from_millis;
+        assert_eq!(clock.offset(), None);
+        assert_eq!(clock.to_exchange(local), local);
+
+        // Exchange clock is about 250ms behind, response is delayed by 50ms.
+        clock.add_sample(local, ms(60), local - ms(240));
+        assert_eq!(
+            clock.offset(),
+            Some(ClockOffset { offset_nanos: -269_500_000, uncertainty: ms(30) + Duration::from_micros(500) }),
+        );
+
+        // Shorter round trip gives better estimate.
+        clock.add_sample(local, ms(10), local - ms(245));
+        let offset = clock.offset().unwrap();
+        assert_eq!(offset.offset_nanos, -249_500_000);
+        assert_eq!(offset.uncertainty, ms(5) + Duration::from_micros(500));
+        assert_eq!(clock.to_exchange(local), local - Duration::from_nanos(249_500_000));
+
+        // Longer one doesn't replace it.
+        clock.add_sample(local, ms(100), local - ms(200));
+        assert_eq!(clock.offset().unwrap(), offset);
+    }
+}
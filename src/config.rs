@@ -1,9 +1,65 @@
-use crate::{Exchange, Pair, Place, Platform};
+use crate::rate_limit::{LocalBackend, RateLimitBackend};
+use crate::{Exchange, Pair, Place, Platform, SideKind};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use std::time::Duration;
 
+/// How websocket connection distributes events between pairs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionMode {
+    /// Every pair is synced by its own task fed through a channel.
+    #[default]
+    TaskPerPair,
+    /// Connection task syncs all its pairs itself, saving wakeups and memory.
+    SingleTask,
+}
+
+/// What to do with a book which breaks its invariants (sorted levels, positive sizes,
+/// capacity, no cross) after an update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InvariantPolicy {
+    /// Only log the violation.
+    Log,
+    /// Report the book as not live until an update fixes it.
+    MarkStale,
+    /// Resync the book from a new snapshot.
+    #[default]
+    Resync,
+}
+
+/// Trades streamed along with books, see [`crate::trades`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TradeStream {
+    /// Every trade.
+    Trade,
+    /// Trades of the same taker order at the same price, merged.
+    AggTrade,
+}
+
+/// Levels of partial depth streams, see [`Config::partial_depth`].
+///
+/// <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#partial-book-depth-streams>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartialDepth {
+    Five,
+    Ten,
+    Twenty,
+}
+
+impl PartialDepth {
+    pub fn levels(self) -> usize {
+        match self {
+            Self::Five => 5,
+            Self::Ten => 10,
+            Self::Twenty => 20,
+        }
+    }
+}
+
 pub struct Config {
     pub(crate) place: Place,
     pub(crate) book_cap: usize,
+    pub(crate) side_kind: SideKind,
     pub(crate) pairs: Vec<Pair>,
     pub(crate) system: SystemConfig,
 }
@@ -11,19 +67,57 @@ pub struct Config {
 #[derive(Clone)]
 pub(crate) struct SystemConfig {
     pub(crate) streams_per_connection: usize,
+    pub(crate) connection_mode: ConnectionMode,
     pub(crate) reconnect_delay: Duration,
+    pub(crate) max_reconnect_delay: Duration,
+    pub(crate) connection_attempts: usize,
+    pub(crate) connection_stagger: Duration,
+    pub(crate) connection_rotation: Duration,
+    pub(crate) rotation_overlap: Duration,
+    pub(crate) ping_interval: Duration,
+    pub(crate) connection_timeout: Duration,
+    pub(crate) pair_timeout_updates: u32,
+    pub(crate) pair_timeouts: HashMap<Pair, Duration>,
     pub(crate) log_prefix: String,
     pub(crate) update_speed: String,
     pub(crate) max_latency: Duration,
     pub(crate) latency_check_interval: Duration,
     pub(crate) max_latency_error: Duration,
+    pub(crate) rate_limit_backend: Arc<dyn RateLimitBackend>,
+    pub(crate) priorities: HashMap<Pair, u64>,
+    pub(crate) full_snapshot_pairs: usize,
+    pub(crate) reduced_snapshot_limit: Option<usize>,
+    pub(crate) max_snapshot_wait: Duration,
+    pub(crate) max_buffered_events: usize,
+    pub(crate) clock_sync_interval: Duration,
+    pub(crate) ready_min_live_ratio: f64,
+    pub(crate) ready_max_event_age: Duration,
+    pub(crate) stream_endpoints: Vec<String>,
+    pub(crate) redundant_pairs: HashSet<Pair>,
+    pub(crate) redundant_feeds: usize,
+    pub(crate) max_feed_gap_wait: Duration,
+    pub(crate) audit_interval: Option<Duration>,
+    pub(crate) invariant_policy: InvariantPolicy,
+    pub(crate) min_valid_depth: usize,
+    pub(crate) book_ticker: bool,
+    pub(crate) partial_depths: HashMap<Pair, PartialDepth>,
+    pub(crate) trade_stream: Option<TradeStream>,
+    pub(crate) trade_buffer: usize,
 }
 
 impl Config {
     pub fn new(place: Place, book_cap: usize, pairs: Vec<Pair>) -> Self {
         let system = SystemConfig::new(&place);
         
-        Self { place, book_cap, pairs, system }
+        Self { place, book_cap, side_kind: SideKind::default(), pairs, system }
+    }
+
+    /// Consider [`SideKind::BTree`] for deep books.
+    #[must_use]
+    pub fn side_kind(mut self, side_kind: SideKind) -> Self {
+        self.side_kind = side_kind;
+
+        self
     }
 
     #[must_use]
@@ -33,6 +127,15 @@ impl Config {
         self
     }
 
+    #[must_use]
+    pub fn connection_mode(mut self, connection_mode: ConnectionMode) -> Self {
+        self.system.connection_mode = connection_mode;
+
+        self
+    }
+
+    /// Delay of the first reconnect, doubled by every failed attempt
+    /// up to [`Config::max_reconnect_delay`] and randomized by half.
     #[must_use]
     pub fn reconnect_delay(mut self, reconnect_delay: Duration) -> Self {
         self.system.reconnect_delay = reconnect_delay;
@@ -40,6 +143,81 @@ impl Config {
         self
     }
 
+    #[must_use]
+    pub fn max_reconnect_delay(mut self, max_reconnect_delay: Duration) -> Self {
+        self.system.max_reconnect_delay = max_reconnect_delay;
+
+        self
+    }
+
+    /// Connection attempts of the Place per 5 minutes, shared through
+    /// [`Config::rate_limit_backend`] with other processes of the same IP.
+    #[must_use]
+    pub fn connection_attempts(mut self, connection_attempts: usize) -> Self {
+        self.system.connection_attempts = connection_attempts;
+
+        self
+    }
+
+    /// Minimal interval between connection attempts of the Place,
+    /// so reconnecting connections don't resync all their pairs at once.
+    #[must_use]
+    pub fn connection_stagger(mut self, connection_stagger: Duration) -> Self {
+        self.system.connection_stagger = connection_stagger;
+
+        self
+    }
+
+    /// Connection is replaced by a new one after that, before the exchange drops it.
+    #[must_use]
+    pub fn connection_rotation(mut self, connection_rotation: Duration) -> Self {
+        self.system.connection_rotation = connection_rotation;
+
+        self
+    }
+
+    /// Both connections stream events for that long before the old one is closed.
+    #[must_use]
+    pub fn rotation_overlap(mut self, rotation_overlap: Duration) -> Self {
+        self.system.rotation_overlap = rotation_overlap;
+
+        self
+    }
+
+    /// Connections are pinged that often, and pairs are checked for inactivity.
+    #[must_use]
+    pub fn ping_interval(mut self, ping_interval: Duration) -> Self {
+        self.system.ping_interval = ping_interval;
+
+        self
+    }
+
+    /// Connection without any frames, including pongs, for that long is considered stalled
+    /// and reconnected, its books are marked stale meanwhile.
+    #[must_use]
+    pub fn connection_timeout(mut self, connection_timeout: Duration) -> Self {
+        self.system.connection_timeout = connection_timeout;
+
+        self
+    }
+
+    /// Live pair without events for that many update intervals (see [`Config::update_speed`])
+    /// is resynced.
+    #[must_use]
+    pub fn pair_timeout_updates(mut self, pair_timeout_updates: u32) -> Self {
+        self.system.pair_timeout_updates = pair_timeout_updates;
+
+        self
+    }
+
+    /// Overrides [`Config::pair_timeout_updates`] for the pair.
+    #[must_use]
+    pub fn pair_timeout(mut self, pair: Pair, timeout: Duration) -> Self {
+        self.system.pair_timeouts.insert(pair, timeout);
+
+        self
+    }
+
     #[must_use]
     pub fn log_prefix(mut self, log_prefix: String) -> Self {
         self.system.log_prefix = log_prefix;
@@ -74,23 +252,253 @@ impl Config {
 
         self
     }
+
+    /// Share REST rate limits with other processes of the same IP.
+    #[must_use]
+    pub fn rate_limit_backend(mut self, rate_limit_backend: Arc<dyn RateLimitBackend>) -> Self {
+        self.system.rate_limit_backend = rate_limit_backend;
+
+        self
+    }
+
+    /// Pairs with higher priority get snapshots first.
+    /// Defaults to 24 hour volume in quote asset, truncated.
+    #[must_use]
+    pub fn pair_priority(mut self, pair: Pair, priority: u64) -> Self {
+        self.system.priorities.insert(pair, priority);
+
+        self
+    }
+
+    /// Number of highest priority pairs which get snapshots of full book capacity
+    /// when [`Config::reduced_snapshot_limit`] is set.
+    #[must_use]
+    pub fn full_snapshot_pairs(mut self, full_snapshot_pairs: usize) -> Self {
+        self.system.full_snapshot_pairs = full_snapshot_pairs;
+
+        self
+    }
+
+    /// Snapshot depth of the rest pairs, smaller snapshots cost less weight.
+    /// Their books are complete only up to that depth until updates fill them.
+    /// Disabled by default.
+    #[must_use]
+    pub fn reduced_snapshot_limit(mut self, reduced_snapshot_limit: usize) -> Self {
+        self.system.reduced_snapshot_limit = Some(reduced_snapshot_limit);
+
+        self
+    }
+
+    /// Snapshot request waiting longer than that is served regardless of priority.
+    #[must_use]
+    pub fn max_snapshot_wait(mut self, max_snapshot_wait: Duration) -> Self {
+        self.system.max_snapshot_wait = max_snapshot_wait;
+
+        self
+    }
+
+    /// Events of a pair buffered while its snapshot is being fetched.
+    /// On overflow the buffer is dropped and the pair restarts from a new snapshot.
+    #[must_use]
+    pub fn max_buffered_events(mut self, max_buffered_events: usize) -> Self {
+        self.system.max_buffered_events = max_buffered_events;
+
+        self
+    }
+
+    /// How often exchange clock is sampled to correct latencies,
+    /// see [`PlaceHandle::clock_offset`](crate::PlaceHandle::clock_offset).
+    #[must_use]
+    pub fn clock_sync_interval(mut self, clock_sync_interval: Duration) -> Self {
+        self.system.clock_sync_interval = clock_sync_interval;
+
+        self
+    }
+
+    /// Place is ready when at least this fraction of its books are live,
+    /// see [`crate::health::serve`].
+    #[must_use]
+    pub fn ready_min_live_ratio(mut self, ready_min_live_ratio: f64) -> Self {
+        self.system.ready_min_live_ratio = ready_min_live_ratio;
+
+        self
+    }
+
+    /// Live books without events for longer than that don't count towards readiness.
+    #[must_use]
+    pub fn ready_max_event_age(mut self, ready_max_event_age: Duration) -> Self {
+        self.system.ready_max_event_age = ready_max_event_age;
+
+        self
+    }
+
+    /// Websocket base URIs, feeds of redundant pairs are spread over them in order.
+    #[must_use]
+    pub fn stream_endpoints(mut self, stream_endpoints: Vec<String>) -> Self {
+        self.system.stream_endpoints = stream_endpoints;
+
+        self
+    }
+
+    /// Pair is streamed by [`Config::redundant_feeds`] independent connections,
+    /// whichever delivers an event first is applied.
+    #[must_use]
+    pub fn redundant_pair(mut self, pair: Pair) -> Self {
+        self.system.redundant_pairs.insert(pair);
+
+        self
+    }
+
+    #[must_use]
+    pub fn redundant_feeds(mut self, redundant_feeds: usize) -> Self {
+        self.system.redundant_feeds = redundant_feeds;
+
+        self
+    }
+
+    /// Event of a redundant pair after missed updates waits that long for another feed
+    /// to deliver them before the pair is resynced.
+    #[must_use]
+    pub fn max_feed_gap_wait(mut self, max_feed_gap_wait: Duration) -> Self {
+        self.system.max_feed_gap_wait = max_feed_gap_wait;
+
+        self
+    }
+
+    /// Compare a book with REST snapshot every `audit_interval`, pairs take turns.
+    /// Diverged books are resynced. Audits are skipped while rate limits are busy.
+    #[must_use]
+    pub fn audit_interval(mut self, audit_interval: Duration) -> Self {
+        self.system.audit_interval = Some(audit_interval);
+
+        self
+    }
+
+    #[must_use]
+    pub fn invariant_policy(mut self, invariant_policy: InvariantPolicy) -> Self {
+        self.system.invariant_policy = invariant_policy;
+
+        self
+    }
+
+    /// Book side is resynced once fewer of its levels than that are known to be complete,
+    /// see [`Side::valid_len`](crate::Side::valid_len). Shallower snapshots lower it for the book.
+    #[must_use]
+    pub fn min_valid_depth(mut self, min_valid_depth: usize) -> Self {
+        self.system.min_valid_depth = min_valid_depth;
+
+        self
+    }
+
+    /// Stream best bid and offer of every pair in real time along with its depth,
+    /// see [`SharedBook::bbo`](crate::SharedBook::bbo). Doubles streams per pair.
+    #[must_use]
+    pub fn book_ticker(mut self, book_ticker: bool) -> Self {
+        self.system.book_ticker = book_ticker;
+
+        self
+    }
+
+    /// Pair is streamed by top levels of its book every 100ms,
+    /// which replace the book without REST snapshots and don't cost any weight.
+    /// Such pairs aren't audited or streamed redundantly.
+    #[must_use]
+    pub fn partial_depth(mut self, pair: Pair, depth: PartialDepth) -> Self {
+        self.system.partial_depths.insert(pair, depth);
+
+        self
+    }
+
+    /// Stream trades of every pair on the connections of its book,
+    /// they're available through [`PlaceHandle::trades`](crate::PlaceHandle::trades).
+    #[must_use]
+    pub fn trades(mut self, trade_stream: TradeStream) -> Self {
+        self.system.trade_stream = Some(trade_stream);
+
+        self
+    }
+
+    /// Recent trades kept per pair.
+    #[must_use]
+    pub fn trade_buffer(mut self, trade_buffer: usize) -> Self {
+        self.system.trade_buffer = trade_buffer;
+
+        self
+    }
 }
 
 impl SystemConfig {
-    fn new(place: &Place) -> Self {
+    pub(crate) fn new(place: &Place) -> Self {
         match place {
             (Exchange::Binance, Platform::Spot) => Self {
                 // https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#websocket-limits
                 streams_per_connection: 128,
+                connection_mode: ConnectionMode::default(),
                 // https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#websocket-limits
                 reconnect_delay: Duration::from_secs(1),
+                max_reconnect_delay: Duration::from_secs(60),
+                // https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#websocket-limits
+                connection_attempts: 300,
+                connection_stagger: Duration::from_millis(200),
+                // https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#general-wss-information
+                connection_rotation: Duration::from_secs(23 * 60 * 60),
+                rotation_overlap: Duration::from_secs(60),
+                // https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#websocket-limits
+                ping_interval: Duration::from_secs(10),
+                connection_timeout: Duration::from_secs(30),
+                // Matches `ready_max_event_age`, illiquid pairs are silent for long.
+                pair_timeout_updates: 300,
+                pair_timeouts: HashMap::new(),
                 log_prefix: String::from("[binance] [spot]"),
                 // https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#diff-depth-stream
                 update_speed: String::from("1000ms"),
                 max_latency: Duration::from_secs(5),
                 latency_check_interval: Duration::from_secs(1),
                 max_latency_error: Duration::from_millis(100),
+                rate_limit_backend: Arc::new(LocalBackend::new()),
+                priorities: HashMap::new(),
+                full_snapshot_pairs: 32,
+                reduced_snapshot_limit: None,
+                max_snapshot_wait: Duration::from_secs(60),
+                // 100 seconds of the fastest stream, longer than `max_snapshot_wait`.
+                max_buffered_events: 1_000,
+                clock_sync_interval: Duration::from_secs(60),
+                ready_min_live_ratio: 0.95,
+                // Depth stream is silent while book doesn't change, which takes a while for illiquid pairs.
+                ready_max_event_age: Duration::from_secs(5 * 60),
+                // https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#general-wss-information
+                stream_endpoints: vec![
+                    String::from("wss://data-stream.binance.vision"),
+                    String::from("wss://stream.binance.com:9443"),
+                ],
+                redundant_pairs: HashSet::new(),
+                redundant_feeds: 2,
+                max_feed_gap_wait: Duration::from_millis(500),
+                audit_interval: None,
+                invariant_policy: InvariantPolicy::default(),
+                min_valid_depth: 1,
+                book_ticker: false,
+                partial_depths: HashMap::new(),
+                trade_stream: None,
+                trade_buffer: 1_000,
             }
         }
     }
+
+    /// Interval between diff depth events of an actively traded pair.
+    pub(crate) fn update_interval(&self) -> Duration {
+        Duration::from_millis(self.update_speed.trim_end_matches("ms").parse().unwrap())
+    }
+
+    /// Pairs fitting into a connection along with all their streams.
+    pub(crate) fn pairs_per_connection(&self) -> usize {
+        let streams_per_pair = 1 + usize::from(self.book_ticker) + usize::from(self.trade_stream.is_some());
+
+        (self.streams_per_connection / streams_per_pair).max(1)
+    }
+
+    pub(crate) fn pair_timeout(&self, pair: &Pair) -> Duration {
+        self.pair_timeouts.get(pair).copied()
+            .unwrap_or_else(|| self.update_interval() * self.pair_timeout_updates)
+    }
 }
@@ -1,62 +1,450 @@
+mod arbiter;
+mod audit;
+pub(crate) mod decode;
 mod difference;
 mod info;
+mod instruments;
+mod pair_sync;
 mod pairs;
+mod partial;
+mod rate_limiter;
+mod scheduler;
 mod snapshot;
+mod ticker;
+mod time;
+mod trades;
 
-use crate::{Book, HashMapChunks, LatencyMeter, Order, Pair, SystemConfig, TokenBucket};
+use crate::metrics::{self, Labels};
+use crate::{Backoff, HashMapChunks, Pair, PlaceHandle, ReconnectGovernor, SharedBook, SystemConfig};
+use arbiter::Feed;
+use audit::PairAudit;
 use backon::Retryable;
+use decode::{decode_message, StreamEvent};
+use difference::{Client, Syncs};
+use futures::future::{self, FusedFuture};
+use futures::prelude::*;
+pub use instruments::get_instruments;
 pub use pairs::get_pairs;
-use rust_decimal::Decimal;
-use serde::Deserialize;
+use pair_sync::PairSync;
+use partial::PartialSync;
+use scheduler::SnapshotScheduler;
+use snapshot::Snapshot;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_websockets::Message;
+use trades::PairTrades;
 
-#[derive(Debug, Deserialize)]
-struct Update(Decimal, Decimal);
+fn place_labels() -> Labels {
+    vec![("exchange", String::from("binance")), ("platform", String::from("spot"))]
+}
 
-impl From<Update> for Order {
-    fn from(update: Update) -> Self {
-        Self {
-            price: update.0,
-            size: update.1,
-        }
+fn pair_labels(pair: &Pair) -> Labels {
+    let mut labels = place_labels();
+    labels.push(("pair", pair.to_string()));
+    labels
+}
+
+fn connection_labels(id: usize) -> Labels {
+    let mut labels = place_labels();
+    labels.push(("connection", id.to_string()));
+    labels
+}
+
+/// When a frame was received, by wall clock to compare with exchange times
+/// and by monotonic clock to measure its processing.
+#[derive(Debug, Clone, Copy)]
+struct Received {
+    time: SystemTime,
+    at: Instant,
+}
+
+impl Received {
+    fn now() -> Self {
+        Self { time: SystemTime::now(), at: Instant::now() }
     }
 }
 
+/// State of the Place shared by its connections and pair tasks.
+struct Runtime {
+    place: PlaceHandle,
+    scheduler: SnapshotScheduler,
+    governor: ReconnectGovernor,
+    // Of pairs synced by diff depth streams.
+    audits: HashMap<Pair, PairAudit>,
+}
+
+impl Runtime {
+    fn pair_sync(&self, config: &SystemConfig, pair: &Pair, book: &SharedBook) -> PairSync {
+        let limit = self.scheduler.limit(pair, book.load().capacity());
+        let latency = self.place.latency.recorder(pair);
+        let (health, audit) = (self.place.health.pair(pair), self.audits[pair].clone());
+
+        PairSync::new(config, pair.clone(), book, limit, latency, health, audit)
+    }
+}
+
+async fn next_message(client: Option<&mut Client>) -> Option<Result<Message, tokio_websockets::Error>> {
+    match client {
+        Some(client) => client.next().await,
+        None => future::pending().await,
+    }
+}
+
+enum Step {
+    Message(Message),
+    /// Current connection is closed, with error if it failed.
+    Closed(Option<tokio_websockets::Error>),
+    ReplacementClosed(Option<tokio_websockets::Error>),
+    Connected(Result<Box<Client>, tokio_websockets::Error>),
+    Rotate,
+    Handover,
+    Heartbeat,
+    Snapshot(String, Snapshot),
+}
+
+/// Pings the connection, failure surfaces as an error of its next read.
+async fn ping(client: Option<&mut Client>) {
+    if let Some(client) = client {
+        let _ = client.send(Message::ping("")).await;
+    }
+}
+
+/// Keeps the pairs streamed, replacing the connection before the exchange drops it
+/// and reconnecting it once it stalls.
+///
+/// The replacement is opened ahead of time and both streams are applied for a while,
+/// so pair syncs skip events delivered twice and no book needs a new snapshot.
 async fn loop_connection(
     id: usize,
     config: SystemConfig,
-    books: HashMap<Pair, Arc<Mutex<Book>>>,
-    r_tb: Arc<TokenBucket>,
-    w_tb: Arc<TokenBucket>,
-    lat_tx: mpsc::UnboundedSender<Duration>,
-    _lat_meter: Arc<LatencyMeter>,
+    books: HashMap<Pair, SharedBook>,
+    runtime: Arc<Runtime>,
+    feed: Option<Feed>,
 ) {
+    let prefix = format!("{} connection {id}:", config.log_prefix);
+    let endpoint = &config.stream_endpoints[feed.as_ref().map_or(0, |feed| feed.idx) % config.stream_endpoints.len()];
+    let reconnects = metrics::counter("market_view_reconnects_total", connection_labels(id));
+    let rotations = metrics::counter("market_view_rotations_total", connection_labels(id));
+    let messages = metrics::counter("market_view_messages_received_total", connection_labels(id));
+    let skipped = metrics::counter("market_view_messages_skipped_total", connection_labels(id));
+    let health = runtime.place.health.connection(id);
+    // Redundant pairs stay live on other feeds.
+    let pairs = match feed {
+        None => Vec::from_iter(books.keys().map(|pair| runtime.place.health.pair(pair))),
+        Some(_) => Vec::new(),
+    };
+    let (partial, diff): (HashMap<_, _>, HashMap<_, _>) = books.iter()
+        .map(|(pair, book)| (pair.clone(), book.clone()))
+        .partition(|(pair, _)| config.partial_depths.contains_key(pair));
+    let mut syncs = Syncs::new(&config, &diff, &runtime, feed);
+    // Partial depth events are routed by lowercase symbols of their stream names.
+    let mut partials = HashMap::<_, _>::from_iter(
+        partial.iter().map(|(pair, book)| (
+            pair.fused(),
+            PartialSync::new(pair, book, config.partial_depths[pair].levels(), runtime.place.health.pair(pair))
+        ))
+    );
+    let tickers = HashMap::<_, _>::from_iter(books.iter().map(|(pair, book)| (pair.fused_upper(), book.clone())));
+    let trades = match config.trade_stream {
+        Some(_) => HashMap::from_iter(books.keys().map(|pair| (
+            pair.fused_upper(),
+            PairTrades::new(pair, runtime.place.trades[pair].clone())
+        ))),
+        None => HashMap::new(),
+    };
+
+    let connect = |delay| {
+        let (config, books, governor) = (&config, &books, &runtime.governor);
+        async move {
+            tokio::time::sleep(delay).await;
+            governor.acquire().await;
+            difference::connect(config, endpoint, books).await
+        }.fuse()
+    };
+    let mut current = None;
+    // Replacement of `current` while they overlap.
+    let mut next = None;
+    // When connections received their last frames.
+    let (mut current_seen, mut next_seen) = (Instant::now(), Instant::now());
+    let mut connected_at = Instant::now();
+    let mut backoff = Backoff::new(config.reconnect_delay, config.max_reconnect_delay);
+    let mut connecting = pin!(connect(Duration::ZERO));
+    let mut rotate = pin!(tokio::time::sleep(config.connection_rotation));
+    let mut handover = pin!(tokio::time::sleep(config.rotation_overlap));
+    let mut heartbeat = tokio::time::interval(config.ping_interval);
+    // Payloads are split off the read buffer and shared with it,
+    // so `simd-json` parses their copies in a reused buffer instead of allocating per frame.
+    #[cfg(feature = "simd-json")]
+    let mut scratch = Vec::new();
+    // Frames of symbols the connection didn't subscribe to.
+    let skip_unknown = |symbol: &str| {
+        log_event!(warn, prefix, { symbol }, "skipping frame of unknown symbol {symbol}");
+        skipped.inc();
+    };
+
     loop {
-        match difference::run_connection(&config, &books, &r_tb, &w_tb, &lat_tx).await {
-            Ok(()) => log::info!("{} connection {id}: restarting", config.log_prefix),
-            Err(err) => log::error!("{} connection {id}: {err:?}", config.log_prefix),
+        let rotating = next.is_some() || !connecting.is_terminated();
+        let step = tokio::select! {
+            msg = next_message(current.as_deref_mut()) => match msg {
+                Some(Ok(msg)) => {
+                    current_seen = Instant::now();
+                    Step::Message(msg)
+                }
+                end => Step::Closed(end.and_then(Result::err)),
+            },
+            msg = next_message(next.as_deref_mut()) => match msg {
+                Some(Ok(msg)) => {
+                    next_seen = Instant::now();
+                    Step::Message(msg)
+                }
+                end => Step::ReplacementClosed(end.and_then(Result::err)),
+            },
+            client = &mut connecting => Step::Connected(client.map(Box::new)),
+            () = &mut rotate, if current.is_some() && !rotating => Step::Rotate,
+            () = &mut handover, if next.is_some() => Step::Handover,
+            _ = heartbeat.tick() => Step::Heartbeat,
+            (symbol, shot) = syncs.next_snapshot() => Step::Snapshot(symbol, shot),
         };
-        tokio::time::sleep(config.reconnect_delay).await;
+        // Current connection is gone, either closed or stalled.
+        let mut lost = false;
+
+        match step {
+            Step::Message(msg) => {
+                let received = Received::now();
+
+                if msg.is_text() || msg.is_binary() {
+                    messages.inc();
+                    #[cfg(not(feature = "simd-json"))]
+                    let body = &**msg.as_payload();
+                    #[cfg(feature = "simd-json")]
+                    let body = {
+                        scratch.clear();
+                        scratch.extend_from_slice(msg.as_payload());
+                        &mut scratch[..]
+                    };
+
+                    match decode_message(body) {
+                        Ok((symbol, StreamEvent::Depth(event))) => syncs.on_event(&prefix, symbol, event, received),
+                        Ok((symbol, StreamEvent::Partial(event))) => match partials.get_mut(symbol) {
+                            Some(partial) => partial.on_event(event, received.time),
+                            None => skip_unknown(symbol),
+                        },
+                        Ok((symbol, StreamEvent::Ticker(bbo))) => match tickers.get(symbol) {
+                            Some(book) => book.set_ticker(bbo),
+                            None => skip_unknown(symbol),
+                        },
+                        Ok((symbol, StreamEvent::Trade(trade))) => match trades.get(symbol) {
+                            Some(trades) => trades.on_trade(&config, trade),
+                            None => skip_unknown(symbol),
+                        },
+                        // Subscription results, errors and changes of the format.
+                        Err(err) => {
+                            let frame = String::from_utf8_lossy(body);
+                            log_event!(warn, prefix, { ?err, %frame }, "skipping undecodable frame {frame}: {err:?}");
+                            skipped.inc();
+                        }
+                    }
+                }
+            }
+            Step::Closed(err) => {
+                match err {
+                    Some(err) => log_event!(error, prefix, { ?err }, "connection failed: {err:?}"),
+                    None => log_event!(info, prefix, {}, "connection closed"),
+                }
+                lost = true;
+            }
+            Step::ReplacementClosed(err) => {
+                log_event!(warn, prefix, { ?err }, "replacement connection closed, retrying rotation: {err:?}");
+                next = None;
+                rotate.as_mut().reset(Instant::now() + backoff.next());
+            }
+            Step::Connected(Ok(client)) => {
+                if current.is_none() {
+                    current = Some(client);
+                    current_seen = Instant::now();
+                    connected_at = Instant::now();
+                    health.set_connected(true);
+                    syncs.set_connected(true);
+                } else {
+                    next = Some(client);
+                    next_seen = Instant::now();
+                    handover.as_mut().reset(Instant::now() + config.rotation_overlap);
+                }
+                rotate.as_mut().reset(Instant::now() + config.connection_rotation);
+            }
+            Step::Connected(Err(err)) => {
+                log_event!(error, prefix, { ?err }, "failed to connect: {err:?}");
+
+                if current.is_none() {
+                    reconnects.inc();
+                    connecting.set(connect(backoff.next()));
+                } else {
+                    rotate.as_mut().reset(Instant::now() + backoff.next());
+                }
+            }
+            Step::Rotate => {
+                log_event!(info, prefix, {}, "opening replacement connection");
+                connecting.set(connect(Duration::ZERO));
+            }
+            Step::Handover => {
+                // Old connection is dropped without closing handshake, it's expiring anyway.
+                current = next.take();
+                current_seen = next_seen;
+                connected_at = Instant::now();
+                backoff.reset();
+                rotations.inc();
+            }
+            Step::Heartbeat => {
+                // Half-open connections don't fail, they just go silent.
+                if next.is_some() && next_seen.elapsed() >= config.connection_timeout {
+                    log_event!(
+                        warn, prefix, { timeout = ?config.connection_timeout },
+                        "replacement connection stalled for {:?}", config.connection_timeout,
+                    );
+                    next = None;
+                    rotate.as_mut().reset(Instant::now() + backoff.next());
+                }
+                if current.is_some() && current_seen.elapsed() >= config.connection_timeout {
+                    log_event!(
+                        error, prefix, { timeout = ?config.connection_timeout },
+                        "no frames for {:?}, reconnecting", config.connection_timeout,
+                    );
+                    lost = true;
+                } else {
+                    ping(current.as_deref_mut()).await;
+                    ping(next.as_deref_mut()).await;
+                }
+                syncs.check_inactive();
+            }
+            Step::Snapshot(symbol, shot) => syncs.on_snapshot(symbol, shot),
+        }
+
+        if lost {
+            // Connection which lasted long enough isn't a part of a series of failures.
+            if connected_at.elapsed() >= config.max_reconnect_delay {
+                backoff.reset();
+            }
+            connected_at = Instant::now();
+            current = next.take();
+            current_seen = next_seen;
+            if current.is_some() {
+                // Replacement is already streaming, so it takes over right away.
+                rotations.inc();
+            } else {
+                // Books keep their sync state, but don't change until the connection is back.
+                for pair in &pairs {
+                    pair.set_live(false);
+                }
+                health.set_connected(false);
+                syncs.set_connected(false);
+                reconnects.inc();
+                if connecting.is_terminated() {
+                    connecting.set(connect(backoff.next()));
+                }
+            }
+        }
     }
 }
 
-pub(crate) async fn spawn(config: SystemConfig, books: HashMap<Pair, Arc<Mutex<Book>>>) {
-    let (r_tb, w_tb) = info::get_rate_limits_tbs
+pub(crate) async fn spawn(config: SystemConfig, place: PlaceHandle) {
+    let books = place.books().clone();
+    let rl = (|| info::get_rate_limiter(&config))
         .retry(backon::ExponentialBuilder::default())
         .await.unwrap();
 
-    let (lat_tx, lat_rx) = mpsc::unbounded_channel();
-    let lat_meter = Arc::new(LatencyMeter::new(
-        config.log_prefix.clone(), config.latency_check_interval, lat_rx,
+    let volumes = if books.keys().all(|pair| config.priorities.contains_key(pair)) {
+        HashMap::new()
+    } else {
+        (|| ticker::get_quote_volumes(&config, &rl))
+            .retry(backon::ExponentialBuilder::default())
+            .await
+            .unwrap_or_else(|err| {
+                log_event!(
+                    warn, config.log_prefix, { ?err }, "24hr ticker request failed, pairs get no priority: {err:?}",
+                );
+                HashMap::new()
+            })
+    };
+    let priorities = HashMap::from_iter(
+        books.keys().map(|pair| (
+            pair.clone(),
+            config.priorities.get(pair).copied()
+                .unwrap_or_else(|| volumes.get(&pair.fused_upper()).copied().unwrap_or(0))
+        ))
+    );
+    let clock = Arc::clone(&place.clock);
+    spawn_in_current_span!(time::run_clock_sync(config.clone(), Arc::clone(&rl), Arc::clone(&clock)));
+
+    place.health.set_headroom(Box::new({
+        let rl = Arc::clone(&rl);
+        move || rl.headroom()
+    }));
+
+    spawn_in_current_span!(place.latency.clone().run_check(
+        config.log_prefix.clone(), config.latency_check_interval, config.max_latency,
     ));
 
-    for (idx, books) in HashMapChunks::new(books, config.streams_per_connection).enumerate() {
-        tokio::spawn(loop_connection(
-            idx + 1, config.clone(), books, Arc::clone(&r_tb), Arc::clone(&w_tb), lat_tx.clone(),
-            Arc::clone(&lat_meter),
-        ));
+    let scheduler = SnapshotScheduler::new(
+        rl, priorities, config.full_snapshot_pairs, config.reduced_snapshot_limit, config.max_snapshot_wait,
+    );
+    // https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#websocket-limits
+    let governor = ReconnectGovernor::new(
+        String::from("binance-spot-ws-connections"), config.connection_attempts, Duration::from_secs(5 * 60),
+        config.connection_stagger, Arc::clone(&config.rate_limit_backend),
+    );
+    let diff = HashMap::<_, _>::from_iter(
+        books.iter()
+            .filter(|(pair, _)| !config.partial_depths.contains_key(pair))
+            .map(|(pair, book)| (pair.clone(), book.clone()))
+    );
+    let audits = HashMap::from_iter(diff.keys().map(|pair| (pair.clone(), PairAudit::default())));
+    let runtime = Arc::new(Runtime { place, scheduler, governor, audits });
+
+    if let Some(interval) = config.audit_interval {
+        spawn_in_current_span!(audit::run_auditor(config.clone(), diff, Arc::clone(&runtime), interval));
+    }
+
+    let (redundant, books): (HashMap<_, _>, HashMap<_, _>) = books.into_iter().partition(|(pair, _)| {
+        config.redundant_pairs.contains(pair) && !config.partial_depths.contains_key(pair)
+    });
+    let mut connections = Vec::from_iter(
+        HashMapChunks::new(books, config.pairs_per_connection()).map(|books| (books, None))
+    );
+
+    if !redundant.is_empty() {
+        let arbiters = HashMap::<_, _>::from_iter(redundant.iter().map(|(pair, book)| {
+            let tx = difference::spawn_pair(&config, pair, book, &runtime);
+            let (arbiter_tx, rx) = mpsc::unbounded_channel();
+            spawn_in_span!(
+                arbiter::run_arbiter(
+                    config.clone(), pair.clone(), config.redundant_feeds, Arc::clone(&clock),
+                    runtime.place.health.pair(pair), rx, tx,
+                ),
+                "arbiter", pair = %pair,
+            );
+            (pair.fused_upper(), arbiter_tx)
+        }));
+
+        for idx in 0..config.redundant_feeds {
+            for books in HashMapChunks::new(redundant.clone(), config.pairs_per_connection()) {
+                let arbiters = HashMap::from_iter(books.keys().map(|pair| {
+                    let symbol = pair.fused_upper();
+                    let tx = arbiters[&symbol].clone();
+                    (symbol, tx)
+                }));
+                connections.push((books, Some(Feed { idx, arbiters })));
+            }
+        }
+    }
+
+    for (idx, (books, feed)) in connections.into_iter().enumerate() {
+        spawn_in_span!(
+            loop_connection(idx + 1, config.clone(), books, Arc::clone(&runtime), feed),
+            "connection", id = idx + 1,
+        );
     }
 }
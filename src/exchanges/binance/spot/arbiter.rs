@@ -0,0 +1,283 @@
+use super::decode::EventPayload;
+use super::{pair_labels, Received};
+use crate::health::PairHealth;
+use crate::metrics::{self, Counter, Histogram};
+use crate::{ClockSync, Pair, SystemConfig};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Message of the feed of the given index to the arbiter of a pair.
+pub(super) enum FeedEvent {
+    Event(usize, Received, EventPayload),
+    /// Connection of the feed went up or down.
+    Connected(usize, bool),
+}
+
+/// One of redundant connections streaming the same pairs.
+pub(super) struct Feed {
+    pub(super) idx: usize,
+    /// Arbiters of the pairs by symbol.
+    pub(super) arbiters: HashMap<String, mpsc::UnboundedSender<FeedEvent>>,
+}
+
+struct FeedMetrics {
+    events: Counter,
+    /// Events forwarded from this feed because it delivered them first.
+    wins: Counter,
+    latency: Histogram,
+}
+
+struct Pending {
+    feed: usize,
+    since: Instant,
+    received: Received,
+    event: EventPayload,
+}
+
+/// Merges events of a pair from redundant feeds into one sequence for its sync.
+///
+/// Every update is forwarded as soon as the first feed delivers it, later copies are dropped.
+/// Event which doesn't follow forwarded ones waits for other feeds to deliver
+/// the missing updates, and is forwarded after `max_feed_gap_wait` anyway,
+/// so the sync resyncs the pair only if every feed missed them.
+struct Arbiter<'a> {
+    pair: &'a Pair,
+    clock: &'a ClockSync,
+    health: PairHealth,
+    // Whether connections of the feeds are up.
+    connected: Vec<bool>,
+    // Zero until the first event.
+    last_u: u64,
+    // Events after a gap by their first update id.
+    pending: BTreeMap<u64, Pending>,
+    feeds: Vec<FeedMetrics>,
+}
+
+impl<'a> Arbiter<'a> {
+    fn new(pair: &'a Pair, clock: &'a ClockSync, health: PairHealth, feeds: usize) -> Self {
+        let feeds = Vec::from_iter((0..feeds).map(|feed| {
+            let mut labels = pair_labels(pair);
+            labels.push(("feed", feed.to_string()));
+
+            FeedMetrics {
+                events: metrics::counter("market_view_feed_events_total", labels.clone()),
+                wins: metrics::counter("market_view_feed_wins_total", labels.clone()),
+                latency: metrics::histogram("market_view_feed_latency_seconds", labels),
+            }
+        }));
+
+        Self { pair, clock, health, connected: vec![false; feeds.len()], last_u: 0, pending: BTreeMap::new(), feeds }
+    }
+
+    /// Marks the book stale once every feed is disconnected,
+    /// it's live again after the next event applied by its sync.
+    fn set_connected(&mut self, config: &SystemConfig, feed: usize, connected: bool) {
+        self.connected[feed] = connected;
+
+        if !self.connected.contains(&true) {
+            let pair = self.pair;
+            log_event!(
+                warn, format_args!("{} [{pair}]:", config.log_prefix), { %pair }, "every feed is disconnected",
+            );
+            self.health.set_live(false);
+        }
+    }
+
+    fn deadline(&self, max_wait: Duration) -> Option<Instant> {
+        self.pending.values().next().map(|pending| pending.since + max_wait)
+    }
+
+    fn forward(
+        &mut self,
+        feed: usize,
+        received: Received,
+        event: EventPayload,
+        out: &mut impl FnMut(Received, EventPayload),
+    ) {
+        self.feeds[feed].wins.inc();
+        self.last_u = event.u;
+        out(received, event);
+    }
+
+    /// Forwards pending events which follow the forwarded ones now.
+    fn drain(&mut self, out: &mut impl FnMut(Received, EventPayload)) {
+        while let Some(entry) = self.pending.first_entry() {
+            let Pending { event, .. } = entry.get();
+
+            if event.u <= self.last_u {
+                entry.remove();
+            } else if event.U <= self.last_u + 1 {
+                let Pending { feed, received, event, .. } = entry.remove();
+                self.forward(feed, received, event, out);
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn on_event(
+        &mut self,
+        feed: usize,
+        received: Received,
+        event: EventPayload,
+        now: Instant,
+        out: &mut impl FnMut(Received, EventPayload),
+    ) {
+        let metrics = &self.feeds[feed];
+        metrics.events.inc();
+        metrics.latency.observe(
+            self.clock.to_exchange(received.time)
+                .duration_since(UNIX_EPOCH + Duration::from_millis(event.E))
+                .unwrap_or_default()
+        );
+
+        if event.u <= self.last_u {
+            // Another feed was faster.
+            return;
+        }
+        if self.last_u > 0 && event.U > self.last_u + 1 {
+            // This feed missed some updates, another one may still deliver them.
+            self.pending.entry(event.U).or_insert(Pending { feed, since: now, received, event });
+            return;
+        }
+        self.forward(feed, received, event, out);
+        self.drain(out);
+    }
+
+    /// Gives up on the missing updates if no feed delivered them in time.
+    fn expire(&mut self, config: &SystemConfig, now: Instant, out: &mut impl FnMut(Received, EventPayload)) {
+        while self.deadline(config.max_feed_gap_wait).is_some_and(|deadline| deadline <= now) {
+            let (_, Pending { feed, received, event, .. }) = self.pending.pop_first().unwrap();
+            let pair = self.pair;
+            log_event!(
+                warn, format_args!("{} [{pair}]:", config.log_prefix), { %pair, U = event.U, last_u = self.last_u },
+                "every feed missed updates after {}, next one is {}", self.last_u, event.U,
+            );
+
+            self.forward(feed, received, event, out);
+            self.drain(out);
+        }
+    }
+}
+
+/// Arbitrates events of `pair` from `feeds` redundant feeds received by `rx`
+/// and passes them to its sync by `tx`.
+pub(super) async fn run_arbiter(
+    config: SystemConfig,
+    pair: Pair,
+    feeds: usize,
+    clock: Arc<ClockSync>,
+    health: PairHealth,
+    mut rx: mpsc::UnboundedReceiver<FeedEvent>,
+    tx: mpsc::UnboundedSender<(Received, EventPayload)>,
+) {
+    let mut arbiter = Arbiter::new(&pair, &clock, health, feeds);
+    // Events sent after the sync task stopped are dropped, the arbiter stops before the next step.
+    let mut out = |received, event| {
+        let _ = tx.send((received, event));
+    };
+
+    loop {
+        if tx.is_closed() {
+            log_event!(
+                error, format_args!("{} [{pair}]:", config.log_prefix), { %pair },
+                "sync task stopped, stopping the arbiter",
+            );
+            return;
+        }
+        let deadline = arbiter.deadline(config.max_feed_gap_wait);
+
+        tokio::select! {
+            event = rx.recv() => match event {
+                Some(FeedEvent::Event(feed, received, event)) => {
+                    arbiter.on_event(feed, received, event, Instant::now(), &mut out);
+                }
+                Some(FeedEvent::Connected(feed, connected)) => arbiter.set_connected(&config, feed, connected),
+                None => return,
+            },
+            () = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                arbiter.expire(&config, Instant::now(), &mut out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Exchange, Platform};
+
+    #[test]
+    fn arbitrate() {
+        let config = SystemConfig::new(&(Exchange::Binance, Platform::Spot));
+        let pair = Pair::new(String::from("arbitrated"), String::from("usdt"));
+        let clock = ClockSync::new();
+        let health = PairHealth::default();
+        let mut arbiter = Arbiter::new(&pair, &clock, health.clone(), 2);
+        let mut forwarded = Vec::new();
+        let mut out = |_, event: EventPayload| forwarded.push((event.U, event.u));
+        let start = Instant::now();
+        let event = |first, last| EventPayload { E: 0, U: first, u: last, b: Vec::new(), a: Vec::new() };
+
+        // Both feeds deliver the same events, the first copy wins.
+        arbiter.on_event(0, Received::now(), event(1, 2), start, &mut out);
+        arbiter.on_event(1, Received::now(), event(1, 2), start, &mut out);
+        arbiter.on_event(1, Received::now(), event(3, 4), start, &mut out);
+        arbiter.on_event(0, Received::now(), event(3, 4), start, &mut out);
+        // Feed 0 misses 5..=6, feed 1 fills the gap.
+        arbiter.on_event(0, Received::now(), event(7, 8), start, &mut out);
+        arbiter.on_event(1, Received::now(), event(5, 6), start, &mut out);
+        arbiter.on_event(1, Received::now(), event(7, 8), start, &mut out);
+        // Both miss 9..=10, so the next event waits until it's forwarded as is.
+        arbiter.on_event(1, Received::now(), event(11, 12), start, &mut out);
+        arbiter.expire(&config, start + config.max_feed_gap_wait / 2, &mut out);
+        assert_eq!(arbiter.deadline(config.max_feed_gap_wait), Some(start + config.max_feed_gap_wait));
+        arbiter.expire(&config, start + config.max_feed_gap_wait, &mut out);
+        assert_eq!(arbiter.deadline(config.max_feed_gap_wait), None);
+
+        assert_eq!(forwarded, [(1, 2), (3, 4), (5, 6), (7, 8), (11, 12)]);
+        #[cfg(feature = "metrics")]
+        {
+            let wins = |feed: &str| metrics::counters().into_iter()
+                .find(|c| {
+                    c.name == "market_view_feed_wins_total"
+                        && c.labels.contains(&("pair", pair.to_string()))
+                        && c.labels.contains(&("feed", String::from(feed)))
+                })
+                .unwrap()
+                .value;
+            assert_eq!((wins("0"), wins("1")), (2, 3));
+        }
+
+        // Book stays live until every feed is down.
+        health.set_live(true);
+        arbiter.set_connected(&config, 0, true);
+        arbiter.set_connected(&config, 1, true);
+        arbiter.set_connected(&config, 0, false);
+        assert!(health.live());
+        arbiter.set_connected(&config, 1, false);
+        assert!(!health.live());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stopped_sync() {
+        let config = SystemConfig::new(&(Exchange::Binance, Platform::Spot));
+        let pair = Pair::new(String::from("stopped"), String::from("usdt"));
+        let (feed_tx, feed_rx) = mpsc::unbounded_channel();
+        let (tx, rx) = mpsc::unbounded_channel();
+        let arbiter = tokio::spawn(
+            run_arbiter(config, pair, 2, Arc::new(ClockSync::new()), PairHealth::default(), feed_rx, tx)
+        );
+
+        drop(rx);
+        let event = EventPayload { E: 0, U: 1, u: 2, b: Vec::new(), a: Vec::new() };
+        feed_tx.send(FeedEvent::Event(0, Received::now(), event)).unwrap();
+
+        // Arbiter stops instead of panicking, so feeds drop events of the pair.
+        arbiter.await.unwrap();
+        assert!(feed_tx.is_closed());
+    }
+}
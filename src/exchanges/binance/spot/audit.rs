@@ -0,0 +1,244 @@
+use super::decode::EventPayload;
+use super::scheduler::SnapshotScheduler;
+use super::snapshot::{get_snapshot, Snapshot};
+use super::{pair_labels, Runtime};
+use crate::metrics;
+use crate::{Book, Order, Pair, SharedBook, SystemConfig};
+use rust_decimal::Decimal;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Audit is skipped while REST rate limits are used more than that.
+const MIN_HEADROOM: f64 = 0.5;
+/// Update intervals to wait for the stream to catch up with the snapshot.
+const CATCH_UP_UPDATES: u32 = 5;
+
+/// Book right after an event with update ids `first..=last`.
+struct Recorded {
+    first: u64,
+    last: u64,
+    // Prices changed by the event.
+    touched: Vec<Decimal>,
+    book: Arc<Book>,
+}
+
+#[derive(Default)]
+struct AuditCell {
+    recording: AtomicBool,
+    recorded: Mutex<Vec<Recorded>>,
+    diverged: AtomicBool,
+}
+
+/// Audit state of a pair, updated by its sync.
+#[derive(Clone, Default)]
+pub(super) struct PairAudit(Arc<AuditCell>);
+
+/// Prices changed by `event`, bids before asks.
+pub(super) fn touched(event: &EventPayload) -> Vec<Decimal> {
+    Vec::from_iter(event.b.iter().chain(&event.a).map(|order| order.price))
+}
+
+impl PairAudit {
+    /// Whether books should be recorded, as an audit snapshot is being fetched.
+    pub(super) fn recording(&self) -> bool {
+        self.0.recording.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn record(&self, first: u64, last: u64, touched: Vec<Decimal>, book: Arc<Book>) {
+        self.0.recorded.lock().unwrap().push(Recorded { first, last, touched, book });
+    }
+
+    /// Returns whether the last audit found the book diverged, once.
+    pub(super) fn take_diverged(&self) -> bool {
+        self.0.diverged.swap(false, Ordering::Relaxed)
+    }
+
+    fn start(&self) {
+        self.0.recorded.lock().unwrap().clear();
+        self.0.recording.store(true, Ordering::Relaxed);
+    }
+
+    fn reached(&self, update_id: u64) -> bool {
+        self.0.recorded.lock().unwrap().iter().any(|r| r.last >= update_id)
+    }
+
+    fn stop(&self) -> Vec<Recorded> {
+        self.0.recording.store(false, Ordering::Relaxed);
+        std::mem::take(&mut self.0.recorded.lock().unwrap())
+    }
+}
+
+/// Level which differs between the local book and the snapshot.
+#[derive(Debug, Clone, PartialEq)]
+struct Mismatch {
+    bid: bool,
+    price: Decimal,
+    // Zero if the level is missing.
+    local: Decimal,
+    snapshot: Decimal,
+}
+
+/// Compares levels of a side known to be complete on both sides, except `touched` ones.
+/// Snapshot of `limit` levels may be cut off, local side is complete up to `known_worst`.
+fn compare_side(
+    bid: bool,
+    local: impl Iterator<Item = Order>,
+    known_worst: Option<Decimal>,
+    snapshot: &[Order],
+    limit: usize,
+    touched: &[Decimal],
+) -> Vec<Mismatch> {
+    let local = Vec::from_iter(local);
+    // Whether `a` is at least as good as `b`.
+    let as_good = |a: Decimal, b: Decimal| if bid { a >= b } else { a <= b };
+    let covered = |price: Decimal| {
+        snapshot.len() < limit || snapshot.last().is_some_and(|last| as_good(price, last.price))
+    } && known_worst.is_none_or(|known| as_good(price, known));
+
+    let size = |levels: &[Order], price| {
+        levels.iter().find(|o| o.price == price).map_or(Decimal::ZERO, |o| o.size)
+    };
+    let prices = BTreeSet::from_iter(local.iter().chain(snapshot).map(|o| o.price));
+
+    Vec::from_iter(
+        prices.into_iter()
+            .filter(|&price| covered(price) && !touched.contains(&price))
+            .map(|price| Mismatch { bid, price, local: size(&local, price), snapshot: size(snapshot, price) })
+            .filter(|m| m.local != m.snapshot)
+    )
+}
+
+/// Compares the recorded book matching the snapshot, `None` if none matches.
+///
+/// Books are recorded only after events, so the one after the event which covers
+/// the snapshot is compared, except levels changed by that event.
+fn compare(recorded: &[Recorded], snapshot: &Snapshot, limit: usize) -> Option<Vec<Mismatch>> {
+    let id = snapshot.lastUpdateId;
+    let matching = recorded.iter().find(|r| r.first <= id + 1 && id <= r.last)?;
+    let touched = if matching.last == id { &[][..] } else { &matching.touched };
+
+    let (bids, asks) = (matching.book.bids(), matching.book.asks());
+    let mut mismatches = compare_side(true, bids.iter(), bids.known_worst(), &snapshot.bids, limit, touched);
+    mismatches.extend(compare_side(false, asks.iter(), asks.known_worst(), &snapshot.asks, limit, touched));
+    Some(mismatches)
+}
+
+async fn audit(
+    config: &SystemConfig,
+    pair: &Pair,
+    book: &SharedBook,
+    audit: &PairAudit,
+    scheduler: &SnapshotScheduler,
+) {
+    let labels = pair_labels(pair);
+    let result = |result: &str| {
+        let mut labels = labels.clone();
+        labels.push(("result", String::from(result)));
+        metrics::counter("market_view_audits_total", labels).inc();
+    };
+    let limit = scheduler.limit(pair, book.load().capacity());
+
+    audit.start();
+    let snapshot = match get_snapshot(config, pair, limit, scheduler).await {
+        Ok(snapshot) => snapshot,
+        Err(err) => {
+            audit.stop();
+            log_event!(
+                warn, format_args!("{} [{pair}]:", config.log_prefix), { %pair, ?err },
+                "audit snapshot failed: {err:?}",
+            );
+            return;
+        }
+    };
+    // Snapshot may be ahead of the stream.
+    for _ in 0..CATCH_UP_UPDATES {
+        if audit.reached(snapshot.lastUpdateId) {
+            break;
+        }
+        tokio::time::sleep(config.update_interval()).await;
+    }
+
+    match compare(&audit.stop(), &snapshot, limit) {
+        None => result("unmatched"),
+        Some(mismatches) if mismatches.is_empty() => result("ok"),
+        Some(mismatches) => {
+            log_event!(
+                error, format_args!("{} [{pair}]:", config.log_prefix),
+                { %pair, last_update_id = snapshot.lastUpdateId, ?mismatches },
+                "book diverged from snapshot {}, resyncing: {mismatches:?}", snapshot.lastUpdateId,
+            );
+            result("diverged");
+            metrics::counter("market_view_audit_mismatched_levels_total", labels.clone())
+                .add(mismatches.len() as u64);
+            audit.0.diverged.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Audits books one by one every `interval`, comparing them with REST snapshots.
+pub(super) async fn run_auditor(
+    config: SystemConfig,
+    books: HashMap<Pair, SharedBook>,
+    runtime: Arc<Runtime>,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+
+    for (pair, book) in books.iter().cycle() {
+        ticker.tick().await;
+
+        if runtime.scheduler.rate_limiter().headroom() < MIN_HEADROOM {
+            continue;
+        }
+        audit(&config, pair, book, &runtime.audits[pair], &runtime.scheduler).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::order;
+    use crate::SideKind;
+
+    #[test]
+    fn compare_books() {
+        let mut book = Book::new(3, SideKind::Vec);
+        book.bids.shot_update(&[order(10, 1), order(9, 2), order(8, 3)], 3);
+        book.asks.shot_update(&[order(11, 1), order(12, 2)], 2);
+        let recorded = [
+            Recorded { first: 5, last: 6, touched: Vec::new(), book: Arc::new(book.clone()) },
+            // Event changed level 10 after the snapshot.
+            Recorded { first: 7, last: 9, touched: vec![Decimal::from(10)], book: Arc::new(book) },
+        ];
+        let snapshot = |id, bids: &[Order], asks: &[Order]| Snapshot {
+            lastUpdateId: id, bids: bids.to_vec(), asks: asks.to_vec(),
+        };
+
+        // Snapshot is deeper than the local book.
+        let shot = snapshot(6, &[order(10, 1), order(9, 2), order(8, 3), order(7, 4)], &[order(11, 1), order(12, 2)]);
+        assert_eq!(compare(&recorded, &shot, 4), Some(Vec::new()));
+        // Stream didn't cover the snapshot.
+        assert_eq!(compare(&recorded, &snapshot(3, &[], &[]), 4), None);
+
+        let shot = snapshot(8, &[order(10, 5), order(9, 2), order(8, 3)], &[order(11, 1), order(12, 2)]);
+        assert_eq!(compare(&recorded, &shot, 3), Some(Vec::new()));
+
+        // Local book keeps a removed level.
+        let shot = snapshot(6, &[order(10, 1), order(9, 2), order(8, 3)], &[order(11, 1), order(13, 1)]);
+        assert_eq!(compare(&recorded, &shot, 3), Some(vec![
+            Mismatch { bid: false, price: Decimal::from(12), local: Decimal::from(2), snapshot: Decimal::ZERO },
+        ]));
+
+        // Level 7 was never tracked, so local levels beyond 8 aren't compared.
+        let mut book = Book::new(3, SideKind::Vec);
+        book.bids.shot_update(&[order(10, 1), order(9, 2), order(8, 3)], 3);
+        book.bids.diff_update(order(10, 0));
+        book.bids.diff_update(order(6, 1));
+        let recorded = [Recorded { first: 1, last: 2, touched: Vec::new(), book: Arc::new(book) }];
+        let shot = snapshot(2, &[order(9, 2), order(8, 3), order(7, 1), order(6, 1)], &[]);
+        assert_eq!(compare(&recorded, &shot, 4), Some(Vec::new()));
+    }
+}
@@ -0,0 +1,418 @@
+use super::snapshot::Snapshot;
+use crate::trades::{Aggressor, Trade};
+use crate::{Bbo, Order};
+use rust_decimal::Decimal;
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[cfg(not(feature = "simd-json"))]
+pub(crate) type Error = serde_json::Error;
+#[cfg(feature = "simd-json")]
+pub(crate) type Error = simd_json::Error;
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct RawEventPayload<'a> {
+    E: u64,
+    s: &'a str,
+    U: u64,
+    u: u64,
+    #[serde(deserialize_with = "levels")]
+    b: Vec<Order>,
+    #[serde(deserialize_with = "levels")]
+    a: Vec<Order>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct RawTickerPayload<'a> {
+    u: u64,
+    s: &'a str,
+    #[serde(deserialize_with = "decimal")]
+    b: Decimal,
+    #[serde(deserialize_with = "decimal")]
+    B: Decimal,
+    #[serde(deserialize_with = "decimal")]
+    a: Decimal,
+    #[serde(deserialize_with = "decimal")]
+    A: Decimal,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct RawTradePayload<'a> {
+    s: &'a str,
+    t: u64,
+    #[serde(deserialize_with = "decimal")]
+    p: Decimal,
+    #[serde(deserialize_with = "decimal")]
+    q: Decimal,
+    T: u64,
+    // Buyer is the maker.
+    m: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct RawAggTradePayload<'a> {
+    s: &'a str,
+    a: u64,
+    #[serde(deserialize_with = "decimal")]
+    p: Decimal,
+    #[serde(deserialize_with = "decimal")]
+    q: Decimal,
+    T: u64,
+    // Buyer is the maker.
+    m: bool,
+}
+
+fn trade(id: u64, price: Decimal, size: Decimal, time: u64, buyer_maker: bool) -> StreamEvent {
+    StreamEvent::Trade(Trade {
+        id,
+        price,
+        size,
+        aggressor: if buyer_maker { Aggressor::Seller } else { Aggressor::Buyer },
+        time: UNIX_EPOCH + Duration::from_millis(time),
+    })
+}
+
+/// <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#diff-depth-stream>
+#[derive(Debug)]
+#[allow(non_snake_case)]
+pub(crate) struct EventPayload {
+    pub(crate) E: u64,
+    pub(crate) U: u64,
+    pub(crate) u: u64,
+    pub(crate) b: Vec<Order>,
+    pub(crate) a: Vec<Order>,
+}
+
+/// Event of one of the streams of a pair.
+#[derive(Debug)]
+pub(crate) enum StreamEvent {
+    Depth(EventPayload),
+    /// Top levels of the book, which come without symbol, so it's taken from the stream name
+    /// and is lowercase unlike symbols of other events.
+    ///
+    /// <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#partial-book-depth-streams>
+    Partial(Snapshot),
+    /// <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#individual-symbol-book-ticker-streams>
+    Ticker(Bbo),
+    /// <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#trade-streams> \
+    /// <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#aggregate-trade-streams>
+    Trade(Trade),
+}
+
+struct Message<'a> {
+    symbol: &'a str,
+    event: StreamEvent,
+}
+
+enum Stream {
+    Depth,
+    Partial,
+    Ticker,
+    Trade,
+    AggTrade,
+}
+
+/// Symbol and type of stream by its name like `btcusdt@depth5@100ms`, `None` for unknown streams.
+fn parse_stream(name: &str) -> Option<(&str, Stream)> {
+    let (symbol, kind) = name.split_once('@')?;
+
+    let stream = match kind.split_once('@').unwrap_or((kind, "")) {
+        ("depth", "" | "100ms" | "1000ms") => Stream::Depth,
+        ("depth5" | "depth10" | "depth20", "" | "100ms" | "1000ms") => Stream::Partial,
+        ("bookTicker", "") => Stream::Ticker,
+        ("trade", "") => Stream::Trade,
+        ("aggTrade", "") => Stream::AggTrade,
+        _ => return None,
+    };
+    Some((symbol, stream))
+}
+
+impl<'de> Deserialize<'de> for Message<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MessageVisitor;
+
+        impl<'de> Visitor<'de> for MessageVisitor {
+            type Value = Message<'de>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("combined stream message")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                // Stream name comes before data, so data is parsed by its type right away.
+                let mut stream = None;
+                let mut message = None;
+
+                while let Some(key) = map.next_key::<&str>()? {
+                    match key {
+                        "stream" => {
+                            let name = map.next_value::<&str>()?;
+                            stream = Some(parse_stream(name).ok_or_else(|| {
+                                de::Error::invalid_value(de::Unexpected::Str(name), &"known stream name")
+                            })?);
+                        }
+                        "data" => message = Some(match stream {
+                            None => return Err(de::Error::missing_field("stream")),
+                            Some((_, Stream::Depth)) => {
+                                let p = map.next_value::<RawEventPayload>()?;
+                                let event = EventPayload { E: p.E, U: p.U, u: p.u, b: p.b, a: p.a };
+                                Message { symbol: p.s, event: StreamEvent::Depth(event) }
+                            }
+                            Some((symbol, Stream::Partial)) => {
+                                Message { symbol, event: StreamEvent::Partial(map.next_value()?) }
+                            }
+                            Some((_, Stream::Ticker)) => {
+                                let p = map.next_value::<RawTickerPayload>()?;
+                                let bid = Order { price: p.b, size: p.B };
+                                let ask = Order { price: p.a, size: p.A };
+                                Message { symbol: p.s, event: StreamEvent::Ticker(Bbo { update_id: p.u, bid, ask }) }
+                            }
+                            Some((_, Stream::Trade)) => {
+                                let p = map.next_value::<RawTradePayload>()?;
+                                Message { symbol: p.s, event: trade(p.t, p.p, p.q, p.T, p.m) }
+                            }
+                            Some((_, Stream::AggTrade)) => {
+                                let p = map.next_value::<RawAggTradePayload>()?;
+                                Message { symbol: p.s, event: trade(p.a, p.p, p.q, p.T, p.m) }
+                            }
+                        }),
+                        _ => {
+                            map.next_value::<de::IgnoredAny>()?;
+                        }
+                    }
+                }
+
+                message.ok_or_else(|| de::Error::missing_field("data"))
+            }
+        }
+
+        deserializer.deserialize_map(MessageVisitor)
+    }
+}
+
+/// Decodes combined stream message into the symbol and event.
+///
+/// Symbol is borrowed from `body` and levels are parsed straight into orders,
+/// so the only allocations of depth events are the two level vectors.
+/// Levels aren't applied to the book while parsing: [`BookWriter`](crate::BookWriter)
+/// replays every update on its other copy and syncs may buffer or hand events to their tasks,
+/// so they own the levels either way.
+#[cfg(not(feature = "simd-json"))]
+pub(crate) fn decode_message(body: &[u8]) -> Result<(&str, StreamEvent), Error> {
+    let message = serde_json::from_slice::<Message>(body)?;
+
+    Ok((message.symbol, message.event))
+}
+
+/// Decodes combined stream message into the symbol and event, using `body` as a scratch buffer.
+#[cfg(feature = "simd-json")]
+pub(crate) fn decode_message(body: &mut [u8]) -> Result<(&str, StreamEvent), Error> {
+    let message = simd_json::serde::from_slice::<Message>(body)?;
+
+    Ok((message.symbol, message.event))
+}
+
+/// Fast path for plain decimals like Binance's `"104321.98000000"`,
+/// falls back to the generic parser for anything else.
+pub(crate) fn parse_decimal(s: &str) -> Option<Decimal> {
+    let bytes = s.as_bytes();
+
+    // At most 19 digits fit into u64.
+    if bytes.is_empty() || bytes.len() > 19 {
+        return Decimal::from_str(s).ok();
+    }
+
+    let mut mantissa = 0_u64;
+    let mut scale = 0;
+    let mut dot = false;
+    let mut digits = 0;
+
+    for &b in bytes {
+        match b {
+            b'0'..=b'9' => {
+                mantissa = mantissa * 10 + (b - b'0') as u64;
+                digits += 1;
+                if dot {
+                    scale += 1;
+                }
+            }
+            b'.' if !dot => dot = true,
+            _ => return Decimal::from_str(s).ok(),
+        }
+    }
+    if digits == 0 {
+        return None;
+    }
+
+    Some(Decimal::from_i128_with_scale(mantissa as i128, scale))
+}
+
+/// Deserializes `"price"` or `"size"`.
+fn decimal<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+    let s = <&str>::deserialize(deserializer)?;
+
+    parse_decimal(s).ok_or_else(|| de::Error::invalid_value(de::Unexpected::Str(s), &"decimal"))
+}
+
+/// Deserializes `[["price", "size"], ...]` levels.
+pub(crate) fn levels<'de, D>(deserializer: D) -> Result<Vec<Order>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct LevelsVisitor;
+
+    impl<'de> Visitor<'de> for LevelsVisitor {
+        type Value = Vec<Order>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("list of [price, size] levels")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut orders = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+
+            while let Some(Level(order)) = seq.next_element()? {
+                orders.push(order);
+            }
+
+            Ok(orders)
+        }
+    }
+
+    deserializer.deserialize_seq(LevelsVisitor)
+}
+
+struct Level(Order);
+
+impl<'de> Deserialize<'de> for Level {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct LevelVisitor;
+
+        impl<'de> Visitor<'de> for LevelVisitor {
+            type Value = Level;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("[price, size] level")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut next = || -> Result<Decimal, A::Error> {
+                    let s = seq.next_element::<&str>()?
+                        .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+                    parse_decimal(s)
+                        .ok_or_else(|| de::Error::invalid_value(de::Unexpected::Str(s), &self))
+                };
+                let price = next()?;
+                let size = next()?;
+
+                Ok(Level(Order { price, size }))
+            }
+        }
+
+        deserializer.deserialize_seq(LevelVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn decimal() {
+        for s in ["104321.98000000", "0.00001000", "42", "0", "1.", "12345678901234567890.5", "1e-3"] {
+            assert_eq!(parse_decimal(s), Decimal::from_str(s).ok(), "{s}");
+        }
+        assert_eq!(parse_decimal("0.01000000").unwrap().scale(), 8);
+        assert_eq!(parse_decimal(""), None);
+        assert_eq!(parse_decimal("."), None);
+    }
+
+    #[test]
+    fn event() {
+        let mut body = br#"{"stream":"btcusdt@depth@100ms","data":{"e":"depthUpdate","E":1737000000000,"s":"BTCUSDT","U":157,"u":160,"b":[["0.0024","10"]],"a":[["0.0026","100"],["0.0027","0.00000000"]]}}"#.to_vec();
+        let (symbol, StreamEvent::Depth(event)) = decode_message(body.as_mut_slice()).unwrap() else {
+            panic!("not a depth event");
+        };
+
+        assert_eq!(symbol, "BTCUSDT");
+        assert_eq!((event.E, event.U, event.u), (1737000000000, 157, 160));
+        assert_eq!(event.b, vec![Order { price: dec!(0.0024), size: dec!(10) }]);
+        assert_eq!(event.a, vec![
+            Order { price: dec!(0.0026), size: dec!(100) },
+            Order { price: dec!(0.0027), size: dec!(0) },
+        ]);
+    }
+
+    #[test]
+    fn partial() {
+        let mut body = br#"{"stream":"bnbbtc@depth5@100ms","data":{"lastUpdateId":160,"bids":[["0.0024","10"]],"asks":[["0.0026","100"]]}}"#.to_vec();
+        let (symbol, StreamEvent::Partial(event)) = decode_message(body.as_mut_slice()).unwrap() else {
+            panic!("not a partial depth event");
+        };
+
+        assert_eq!(symbol, "bnbbtc");
+        assert_eq!(event.lastUpdateId, 160);
+        assert_eq!(event.bids, vec![Order { price: dec!(0.0024), size: dec!(10) }]);
+        assert_eq!(event.asks, vec![Order { price: dec!(0.0026), size: dec!(100) }]);
+    }
+
+    #[test]
+    fn unknown_stream() {
+        assert!(matches!(parse_stream("btcusdt@depth"), Some(("btcusdt", Stream::Depth))));
+        assert!(matches!(parse_stream("btcusdt@depth@100ms"), Some(("btcusdt", Stream::Depth))));
+        assert!(matches!(parse_stream("btcusdt@depth20"), Some(("btcusdt", Stream::Partial))));
+        for name in ["btcusdt", "btcusdt@depth7", "btcusdt@depth@250ms", "btcusdt@kline_1m", "btcusdt@trade@100ms"] {
+            assert!(parse_stream(name).is_none(), "{name}");
+        }
+
+        let mut body = br#"{"stream":"btcusdt@kline_1m","data":{"e":"kline","E":1737000000000,"s":"BTCUSDT"}}"#.to_vec();
+        assert!(decode_message(body.as_mut_slice()).is_err());
+    }
+
+    #[test]
+    fn ticker() {
+        let mut body = br#"{"stream":"bnbusdt@bookTicker","data":{"u":400900217,"s":"BNBUSDT","b":"25.35190000","B":"31.21000000","a":"25.36520000","A":"40.66000000"}}"#.to_vec();
+        let (symbol, StreamEvent::Ticker(bbo)) = decode_message(body.as_mut_slice()).unwrap() else {
+            panic!("not a ticker event");
+        };
+
+        assert_eq!(symbol, "BNBUSDT");
+        assert_eq!(bbo, Bbo {
+            update_id: 400900217,
+            bid: Order { price: dec!(25.35190000), size: dec!(31.21000000) },
+            ask: Order { price: dec!(25.36520000), size: dec!(40.66000000) },
+        });
+    }
+
+    #[test]
+    fn trade() {
+        let trade = |body: &[u8]| {
+            let mut body = body.to_vec();
+            let (symbol, StreamEvent::Trade(trade)) = decode_message(body.as_mut_slice()).unwrap() else {
+                panic!("not a trade event");
+            };
+            assert_eq!(symbol, "BNBBTC");
+            trade
+        };
+
+        // Payload may carry ids of buyer and seller orders.
+        let raw = trade(br#"{"stream":"bnbbtc@trade","data":{"e":"trade","E":1672515782136,"s":"BNBBTC","t":12345,"p":"0.001","q":"100","b":88,"a":50,"T":1672515782136,"m":true,"M":true}}"#);
+        assert_eq!(raw, Trade {
+            id: 12345,
+            price: dec!(0.001),
+            size: dec!(100),
+            aggressor: Aggressor::Seller,
+            time: UNIX_EPOCH + Duration::from_millis(1672515782136),
+        });
+
+        let aggregated = trade(br#"{"stream":"bnbbtc@aggTrade","data":{"e":"aggTrade","E":1672515782136,"s":"BNBBTC","a":12345,"p":"0.001","q":"100","f":100,"l":105,"T":1672515782136,"m":false,"M":true}}"#);
+        assert_eq!(aggregated, Trade { aggressor: Aggressor::Buyer, ..raw });
+    }
+}
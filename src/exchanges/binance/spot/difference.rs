@@ -1,218 +1,448 @@
-use super::{snapshot::get_snapshot, Update};
-use crate::{Book, Order, Pair, SystemConfig, TokenBucket};
+use super::arbiter::{Feed, FeedEvent};
+use super::decode::EventPayload;
+use super::pair_sync::PairSync;
+use super::snapshot::{get_snapshot, Snapshot};
+use super::{Received, Runtime, SnapshotScheduler};
+use crate::{ConnectionMode, Pair, SharedBook, SystemConfig, TradeStream};
 use backon::Retryable;
+use futures::future::{self, AbortHandle, Aborted, BoxFuture, Fuse};
 use futures::prelude::*;
-use serde::Deserialize;
+use futures::stream::FuturesUnordered;
 use std::collections::HashMap;
+use std::pin::pin;
 use std::str::FromStr;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, UNIX_EPOCH};
+use std::sync::Arc;
+use tokio::net::TcpStream;
 use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_websockets::{MaybeTlsStream, WebSocketStream};
 
-#[derive(Debug, Deserialize)]
-#[allow(non_snake_case)]
-struct Event {
-    data: EventPayload,
+async fn fetch_snapshot(
+    config: &SystemConfig,
+    pair: &Pair,
+    limit: usize,
+    scheduler: &SnapshotScheduler,
+) -> Snapshot {
+    (|| get_snapshot(config, pair, limit, scheduler))
+        .retry(backon::ExponentialBuilder::default())
+        .await.unwrap()
 }
 
-#[derive(Debug, Deserialize)]
-#[allow(non_snake_case)]
-struct EventPayload {
-    E: u64,
-    s: String,
-    U: u64,
-    u: u64,
-    b: Vec<Update>,
-    a: Vec<Update>,
+async fn run_pair<F: Future<Output = Snapshot>>(
+    config: SystemConfig,
+    mut sync: PairSync,
+    mut rx: mpsc::UnboundedReceiver<(Received, EventPayload)>,
+    fetch: impl Fn() -> F,
+) {
+    // Events are drained from the channel into the bounded buffer of `sync`
+    // while snapshot is being fetched.
+    let mut snapshot = pin!(Fuse::terminated());
+    let mut heartbeat = tokio::time::interval(config.ping_interval);
+
+    loop {
+        let resync = tokio::select! {
+            event = rx.recv() => match event {
+                Some((received, event)) => sync.on_event(&config, event, received),
+                None => return,
+            },
+            shot = &mut snapshot => sync.on_snapshot(&config, shot),
+            now = heartbeat.tick() => sync.check_inactive(&config, now),
+        };
+        if resync {
+            snapshot.set(fetch().fuse());
+        }
+    }
 }
 
-fn check_latency(
+/// Spawns task syncing the book of `pair` from events sent to the returned channel.
+pub(super) fn spawn_pair(
     config: &SystemConfig,
     pair: &Pair,
-    event: &EventPayload,
-    tx: &mpsc::UnboundedSender<Duration>
-) {
-    let event_time = UNIX_EPOCH + Duration::from_millis(event.E);
-
-    match event_time.elapsed() {
-        Ok(latency) => if latency > config.max_latency {
-            tx.send(latency).unwrap();
+    book: &SharedBook,
+    runtime: &Arc<Runtime>,
+) -> mpsc::UnboundedSender<(Received, EventPayload)> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let sync = runtime.pair_sync(config, pair, book);
+    let limit = sync.snapshot_limit();
+    let fetch = {
+        let (config, pair, runtime) = (config.clone(), pair.clone(), Arc::clone(runtime));
+        move || {
+            let (config, pair, runtime) = (config.clone(), pair.clone(), Arc::clone(&runtime));
+            async move { fetch_snapshot(&config, &pair, limit, &runtime.scheduler).await }
         }
-        Err(err) => if err.duration() > config.max_latency_error {
-            log::warning!("{} [{pair}]: latency error - {:?}", config.log_prefix, err.duration());
-        }
-    }
+    };
+    spawn_in_span!(run_pair(config.clone(), sync, rx, fetch), "pair", pair = %pair);
+
+    tx
 }
 
-fn apply_event(book: &Arc<Mutex<Book>>, event: EventPayload) {
-    let mut book = book.lock().unwrap();
+pub(super) type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
-    for update in event.b {
-        book.bids.diff_update(Order::from(update))
+/// <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams> \
+/// <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#diff-depth-stream> \
+/// <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#partial-book-depth-streams> \
+/// <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#individual-symbol-book-ticker-streams> \
+/// <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#trade-streams>
+pub(super) async fn connect(
+    config: &SystemConfig,
+    endpoint: &str,
+    books: &HashMap<Pair, SharedBook>,
+) -> Result<Client, tokio_websockets::Error> {
+    let mut streams = Vec::from_iter(books.keys().map(|p| match config.partial_depths.get(p) {
+        Some(depth) => format!("{}@depth{}@100ms", p.fused(), depth.levels()),
+        None => format!("{}@depth@{}", p.fused(), config.update_speed),
+    }));
+    if config.book_ticker {
+        streams.extend(books.keys().map(|p| format!("{}@bookTicker", p.fused())));
     }
-    for update in event.a {
-        book.asks.diff_update(Order::from(update))
+    if let Some(trade_stream) = config.trade_stream {
+        let name = match trade_stream {
+            TradeStream::Trade => "trade",
+            TradeStream::AggTrade => "aggTrade",
+        };
+        streams.extend(books.keys().map(|p| format!("{}@{name}", p.fused())));
     }
+    let uri = http::Uri::from_str(&format!("{endpoint}/stream?streams={}", streams.join("/"))).unwrap();
+    let (client, _) = tokio_websockets::ClientBuilder::from_uri(uri).connect().await?;
+
+    Ok(client)
 }
 
-/// <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#how-to-manage-a-local-order-book-correctly>
-///
-/// ### Snapshot and Event Flow
-///
-/// ```text
-///    U-------u
-///    | Event |
-///    +-------+
-///            U-------u
-///            | Event |
-///            +-------+
-///              U--------------u
-///              |    Event     |
-///              +--------------+
-///                U----------u
-///                | Snapshot |
-///                +----------+
-///                   U-----u
-///                   |Event|
-///                   +-----+
-///                       U-------u
-///                       | Event |
-///                       +-------+
-///                               U-------u
-///                               | Event |
-///                               +-------+
-/// ```
-async fn run_pair(
-    config: SystemConfig,
-    pair: Pair,
-    book: Arc<Mutex<Book>>,
-    mut rx: mpsc::UnboundedReceiver<EventPayload>,
-    r_tb: Arc<TokenBucket>,
-    w_tb: Arc<TokenBucket>,
-    lat_tx: mpsc::UnboundedSender<Duration>,
-) {
-    // Wait until first event appears in case websocket server
-    // will start sending events too late (later than we get snapshot).
-    while rx.is_empty() {
-        if rx.is_closed() {
-            return;
+/// Pair synced by the connection task.
+pub(super) struct InlinePair {
+    sync: PairSync,
+    // Pending snapshot request, superseded by the next one.
+    fetch: Option<AbortHandle>,
+}
+
+/// Sync state of the pairs of a connection, which outlives websocket connections,
+/// so books stay in sync across reconnects and rotations.
+pub(super) enum Syncs<'a> {
+    /// Every pair is synced by its own task.
+    Tasks(HashMap<String, mpsc::UnboundedSender<(Received, EventPayload)>>),
+    /// Events go to arbiters of redundant pairs, which pass them to their tasks.
+    Feed(Feed),
+    /// Pairs are synced by the connection task itself.
+    Inline {
+        config: &'a SystemConfig,
+        scheduler: &'a SnapshotScheduler,
+        syncs: HashMap<String, InlinePair>,
+        snapshots: FuturesUnordered<BoxFuture<'a, Result<(String, Snapshot), Aborted>>>,
+    },
+}
+
+impl<'a> Syncs<'a> {
+    pub(super) fn new(
+        config: &'a SystemConfig,
+        books: &HashMap<Pair, SharedBook>,
+        runtime: &'a Arc<Runtime>,
+        feed: Option<Feed>,
+    ) -> Self {
+        if let Some(feed) = feed {
+            return Self::Feed(feed);
+        }
+        if config.connection_mode == ConnectionMode::SingleTask {
+            let syncs = HashMap::from_iter(
+                books.iter().map(|(p, b)| (
+                    p.fused_upper(),
+                    InlinePair { sync: runtime.pair_sync(config, p, b), fetch: None }
+                ))
+            );
+            let scheduler = &runtime.scheduler;
+            return Self::Inline { config, scheduler, syncs, snapshots: FuturesUnordered::new() };
         }
-        tokio::time::sleep(Duration::from_secs(1)).await;
-    }
 
-    let book_cap = book.lock().unwrap().capacity();
+        Self::Tasks(HashMap::from_iter(
+            books.iter().map(|(p, b)| (p.fused_upper(), spawn_pair(config, p, b, runtime)))
+        ))
+    }
 
-    'from_snapshot: loop {
-        let snapshot = (|| get_snapshot(&pair, book_cap, &r_tb, &w_tb))
-            .retry(backon::ExponentialBuilder::default())
-            .await.unwrap();
+    /// Passes depth event to the pair.
+    /// Events of unknown pairs are dropped, as well as events of pairs whose tasks have stopped,
+    /// as they panicked and were logged.
+    pub(super) fn on_event(&mut self, prefix: &str, symbol: &str, event: EventPayload, received: Received) {
+        let stopped = match self {
+            Self::Tasks(txs) => txs.get(symbol).is_some_and(|tx| tx.send((received, event)).is_err()),
+            Self::Feed(Feed { idx, arbiters }) => {
+                arbiters.get(symbol).is_some_and(|tx| tx.send(FeedEvent::Event(*idx, received, event)).is_err())
+            }
+            Self::Inline { config, syncs, .. } => {
+                let Some(InlinePair { sync, .. }) = syncs.get_mut(symbol) else {
+                    return;
+                };
 
-        {
-            let mut book = book.lock().unwrap();
-
-            let orders = snapshot.bids
-                .into_iter()
-                .map(Order::from)
-                .collect();
-            book.bids.shot_update(orders);
-
-            let orders = snapshot.asks
-                .into_iter()
-                .map(Order::from)
-                .collect();
-            book.asks.shot_update(orders);
-        }
-
-        let mut prev_u;
-
-        loop {
-            match rx.recv().await {
-                Some(event) => {
-                    if event.u <= snapshot.lastUpdateId {
-                        // Snapshot covers this event.
-                        continue;
-                    }
-                    if event.U > snapshot.lastUpdateId + 1 {
-                        // We missed some event.
-                        log::error!(
-                            "{} [{pair}]: U ({}) > lastUpdateId ({}) + 1",
-                            config.log_prefix, event.U, snapshot.lastUpdateId,
-                        );
-                        continue 'from_snapshot;
-                    }
-                    prev_u = event.u;
-
-                    check_latency(&config, &pair, &event, &lat_tx);
-                    apply_event(&book, event);
-                    break;
+                if sync.on_event(config, event, received) {
+                    let symbol = String::from(symbol);
+                    self.fetch(symbol);
                 }
-                None => break 'from_snapshot,
+                false
+            }
+        };
+        if stopped {
+            log_event!(error, prefix, { symbol }, "task of {symbol} stopped, dropping its events");
+            match self {
+                Self::Tasks(txs) => drop(txs.remove(symbol)),
+                Self::Feed(Feed { arbiters, .. }) => drop(arbiters.remove(symbol)),
+                Self::Inline { .. } => {}
+            }
+        }
+    }
+
+    /// Tells arbiters of redundant pairs whether the feed is connected,
+    /// other pairs are marked stale by the connection task.
+    pub(super) fn set_connected(&self, connected: bool) {
+        if let Self::Feed(Feed { idx, arbiters }) = self {
+            for tx in arbiters.values() {
+                // Stopped arbiters are reported by `on_event`.
+                let _ = tx.send(FeedEvent::Connected(*idx, connected));
             }
         }
+    }
+
+    /// Resyncs inline synced pairs without recent events, tasks check their pairs themselves.
+    pub(super) fn check_inactive(&mut self) {
+        let Self::Inline { config, syncs, .. } = self else { return };
+        let now = Instant::now();
+        let inactive = Vec::from_iter(
+            syncs.iter_mut()
+                .filter_map(|(symbol, pair)| pair.sync.check_inactive(config, now).then(|| symbol.clone()))
+        );
 
-        loop {
-            match rx.recv().await {
-                Some(event) => {
-                    if event.U != prev_u + 1 {
-                        // We missed some event.
-                        log::error!(
-                            "{} [{pair}]: U ({}) != prev_u ({prev_u}) + 1",
-                            config.log_prefix, event.U,
-                        );
-                        continue 'from_snapshot;
-                    }
-                    prev_u = event.u;
-
-                    check_latency(&config, &pair, &event, &lat_tx);
-                    apply_event(&book, event);
+        for symbol in inactive {
+            self.fetch(symbol);
+        }
+    }
+
+    /// Next fetched snapshot of inline synced pairs, never ready for tasks.
+    pub(super) async fn next_snapshot(&mut self) -> (String, Snapshot) {
+        if let Self::Inline { snapshots, .. } = self {
+            while let Some(fetched) = snapshots.next().await {
+                if let Ok(fetched) = fetched {
+                    return fetched;
                 }
-                None => break 'from_snapshot,
             }
         }
+        future::pending().await
+    }
+
+    pub(super) fn on_snapshot(&mut self, symbol: String, snapshot: Snapshot) {
+        let Self::Inline { config, syncs, .. } = self else { return };
+        let pair = syncs.get_mut(&symbol).unwrap();
+        pair.fetch = None;
+
+        if pair.sync.on_snapshot(config, snapshot) {
+            self.fetch(symbol);
+        }
+    }
+
+    fn fetch(&mut self, symbol: String) {
+        let Self::Inline { config, scheduler, syncs, snapshots } = self else { return };
+        let (config, scheduler) = (*config, *scheduler);
+        let inline = syncs.get_mut(&symbol).unwrap();
+        let (pair, limit) = (inline.sync.pair().clone(), inline.sync.snapshot_limit());
+
+        let (fetch, abort) = future::abortable(async move {
+            (symbol, fetch_snapshot(config, &pair, limit, scheduler).await)
+        });
+        // Snapshot requested earlier may miss events dropped since.
+        if let Some(previous) = inline.fetch.replace(abort) {
+            previous.abort();
+        }
+        snapshots.push(fetch.boxed());
     }
 }
 
-/// <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams> \
-/// <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#diff-depth-stream>
-pub(super) async fn run_connection(
-    config: &SystemConfig,
-    books: &HashMap<Pair, Arc<Mutex<Book>>>,
-    r_tb: &Arc<TokenBucket>,
-    w_tb: &Arc<TokenBucket>,
-    lat_tx: &mpsc::UnboundedSender<Duration>,
-) -> Result<(), tokio_websockets::Error> {
-    let uri = http::Uri::from_str(&format!(
-        "wss://data-stream.binance.vision/stream?streams={}",
-        books.keys()
-            .map(|p| format!("{}@depth@{}", p.fused(), config.update_speed))
-            .collect::<Vec<String>>()
-            .join("/")
-    )).unwrap();
-    let (mut client, _) =
-        tokio_websockets::ClientBuilder::from_uri(uri).connect().await?;
-
-    let txs = HashMap::<_, _>::from_iter(
-        books.iter().map(|(p, b)| (
-            p.fused_upper(),
-            {
-                let (tx, rx) = mpsc::unbounded_channel();
-                tokio::spawn(run_pair(
-                    config.clone(), p.clone(), Arc::clone(b), rx, Arc::clone(r_tb), Arc::clone(w_tb),
-                    lat_tx.clone()
-                ));
-                tx
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::order;
+    use crate::health::PairHealth;
+    #[cfg(feature = "metrics")]
+    use crate::metrics::{self, Labels};
+    use crate::{Book, Exchange, LatencyRecorder, Order, Platform, SideKind};
+    use super::super::audit::PairAudit;
+    #[cfg(feature = "metrics")]
+    use super::super::pair_labels;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// Task syncing the book of 10 levels of a pair.
+    struct TestPair {
+        book: SharedBook,
+        tx: mpsc::UnboundedSender<(Received, EventPayload)>,
+        task: tokio::task::JoinHandle<()>,
+    }
+
+    impl TestPair {
+        fn spawn<F>(config: SystemConfig, pair: &Pair, fetch: impl Fn() -> F + Send + 'static) -> Self
+        where
+            F: Future<Output = Snapshot> + Send + 'static,
+        {
+            let book = SharedBook::new(Book::new(10, SideKind::Vec));
+            let (tx, rx) = mpsc::unbounded_channel();
+            let sync = PairSync::new(
+                &config, pair.clone(), &book, 10,
+                LatencyRecorder::detached(), PairHealth::default(), PairAudit::default(),
+            );
+            let task = tokio::spawn(run_pair(config, sync, rx, fetch));
+
+            Self { book, tx, task }
+        }
+
+        /// Sends event of updates `first..=last`, which sets the bid at 1 to size of `last`.
+        fn send(&self, first: u64, last: u64) {
+            let now = Received::now();
+            self.tx.send((now, EventPayload {
+                E: now.time.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64,
+                U: first,
+                u: last,
+                b: vec![order(1, last)],
+                a: Vec::new(),
+            })).unwrap();
+        }
+
+        /// Waits until sent events are processed and returns the best bid.
+        async fn finish(self) -> Option<Order> {
+            drop(self.tx);
+            self.task.await.unwrap();
+
+            self.book.load().bids().first()
+        }
+    }
+
+    fn snapshot(last_update_id: u64) -> Snapshot {
+        Snapshot { lastUpdateId: last_update_id, bids: Vec::new(), asks: Vec::new() }
+    }
+
+    /// Sum of the counter over its labels which include `labels`.
+    #[cfg(feature = "metrics")]
+    fn counted(name: &str, labels: Labels) -> u64 {
+        metrics::counters().into_iter()
+            .filter(|c| c.name == name && labels.iter().all(|label| c.labels.contains(label)))
+            .map(|c| c.value)
+            .sum()
+    }
+
+    /// Streams events `1..=600` every 100ms, 50 of which overflow the buffer
+    /// 5s into a 32s snapshot, so the pair restarts from the next one.
+    /// Snapshots take `delays[call]` and reflect the book as of their request (`stale`) or response.
+    /// Returns number of fetched snapshots.
+    async fn simulate(pair: &Pair, delays: [Duration; 2], stale: bool) -> u64 {
+        let mut config = SystemConfig::new(&(Exchange::Binance, Platform::Spot));
+        config.max_buffered_events = 50;
+        let latest = Arc::new(AtomicU64::new(0));
+        let calls = Arc::new(AtomicU64::new(0));
+        let fetch = {
+            let (latest, calls) = (Arc::clone(&latest), Arc::clone(&calls));
+            move || {
+                let (latest, calls) = (Arc::clone(&latest), Arc::clone(&calls));
+                async move {
+                    let requested = latest.load(Ordering::SeqCst);
+                    let call = calls.fetch_add(1, Ordering::SeqCst) as usize;
+                    tokio::time::sleep(delays[call.min(1)]).await;
+
+                    snapshot(if stale { requested } else { latest.load(Ordering::SeqCst) })
+                }
             }
-        ))
-    );
-    while let Some(msg) = client.next().await {
-        let msg = msg?;
-        
-        if msg.is_text() || msg.is_binary() {
-            let body = msg.as_payload();
-            let event = serde_json::from_slice::<Event>(body).unwrap().data;
-            txs[&event.s].send(event).unwrap();
+        };
+        let task = TestPair::spawn(config, pair, fetch);
+
+        for id in 1..=600 {
+            latest.store(id, Ordering::SeqCst);
+            task.send(id, id);
+            tokio::time::sleep(Duration::from_millis(100)).await;
         }
+        // Book is live again and has every event after the snapshot applied.
+        assert_eq!(task.finish().await, Some(order(1, 600)));
+
+        calls.load(Ordering::SeqCst)
     }
 
-    Ok(())
+    #[tokio::test(start_paused = true)]
+    async fn slow_snapshot() {
+        let pair = Pair::new(String::from("slow"), String::from("usdt"));
+
+        assert_eq!(simulate(&pair, [Duration::from_secs(32), Duration::from_secs(1)], false).await, 2);
+        #[cfg(feature = "metrics")]
+        assert_eq!(counted("market_view_snapshot_buffer_overflows_total", pair_labels(&pair)), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stale_snapshot() {
+        let pair = Pair::new(String::from("stale"), String::from("usdt"));
+
+        // Events following the request of the new snapshot are buffered.
+        assert_eq!(simulate(&pair, [Duration::from_secs(32), Duration::from_secs(1)], true).await, 2);
+        #[cfg(feature = "metrics")]
+        assert_eq!(counted("market_view_snapshot_buffer_overflows_total", pair_labels(&pair)), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn overlapping_connections() {
+        // Old connection streams events of 2 updates, its replacement of 3 updates,
+        // and both are applied for a while.
+        let config = SystemConfig::new(&(Exchange::Binance, Platform::Spot));
+        let pair = Pair::new(String::from("rotated"), String::from("usdt"));
+        let calls = Arc::new(AtomicU64::new(0));
+        let fetch = {
+            let calls = Arc::clone(&calls);
+            move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async { snapshot(1) }
+            }
+        };
+        let task = TestPair::spawn(config, &pair, fetch);
+
+        for id in 1..=600 {
+            if id % 2 == 0 && id <= 300 {
+                task.send(id - 1, id);
+            }
+            if id % 3 == 0 && id >= 200 {
+                task.send(id - 2, id);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        assert_eq!(task.finish().await, Some(order(1, 600)));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        #[cfg(feature = "metrics")]
+        assert_eq!(counted("market_view_resyncs_total", pair_labels(&pair)), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn inactive_pair() {
+        let pair = Pair::new(String::from("inactive"), String::from("usdt"));
+        let mut config = SystemConfig::new(&(Exchange::Binance, Platform::Spot));
+        config.pair_timeouts.insert(pair.clone(), Duration::from_secs(5));
+        let latest = Arc::new(AtomicU64::new(0));
+        let calls = Arc::new(AtomicU64::new(0));
+        let fetch = {
+            let (latest, calls) = (Arc::clone(&latest), Arc::clone(&calls));
+            move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                let last_update_id = latest.load(Ordering::SeqCst);
+                async move { snapshot(last_update_id) }
+            }
+        };
+        let task = TestPair::spawn(config, &pair, fetch);
+
+        for id in 1..=20 {
+            // Stream gets stuck for a while.
+            if id == 11 {
+                tokio::time::sleep(Duration::from_secs(20)).await;
+            }
+            latest.store(id, Ordering::SeqCst);
+            task.send(id, id);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        assert_eq!(task.finish().await, Some(order(1, 20)));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        #[cfg(feature = "metrics")]
+        {
+            let mut labels = pair_labels(&pair);
+            labels.push(("reason", String::from("inactivity")));
+            assert_eq!(counted("market_view_resyncs_total", labels), 1);
+        }
+    }
 }
@@ -1,13 +1,20 @@
-use crate::TokenBucket;
+use super::rate_limiter::{RateLimitType, RateLimiter};
+use crate::rate_limit::RateLimitBackend;
+use crate::SystemConfig;
+use serde::de::{DeserializeOwned, IgnoredAny};
 use serde::Deserialize;
-use std::collections::HashMap;
+use serde_json::json;
 use std::sync::Arc;
 use std::time::Duration;
 
+const EXCHANGE_INFO_WEIGHT: u32 = 20;
+
 #[derive(Debug, Deserialize)]
 #[allow(non_snake_case)]
-struct ExchangeInfo {
+struct ExchangeInfo<T> {
     rateLimits: Vec<RateLimit>,
+    #[serde(flatten)]
+    info: T,
 }
 
 #[derive(Debug, Deserialize)]
@@ -19,31 +26,57 @@ struct RateLimit {
     rateLimitType: String,
 }
 
+/// Requests exchange information along with the limiter of its REST limits.
+///
+/// Limits are unknown before the response, so the request itself can't wait for them,
+/// but its weight is charged to the limiter afterwards, which is then reconciled with usage it reports.
+/// Either way other requests sharing the rate limit backend wait for it.
+///
 /// <https://developers.binance.com/docs/binance-spot-api-docs/rest-api/limits> \
 /// <https://developers.binance.com/docs/binance-spot-api-docs/rest-api/general-endpoints#exchange-information>
-pub(super) async fn get_rate_limits_tbs() -> reqwest::Result<(Arc<TokenBucket>, Arc<TokenBucket>)> {
-    let exchange_info = reqwest::Client::new()
+pub(super) async fn get_exchange_info<T: DeserializeOwned>(
+    prefix: &str,
+    rate_limit_backend: Arc<dyn RateLimitBackend>,
+    query: &serde_json::Value,
+) -> reqwest::Result<(T, Arc<RateLimiter>)> {
+    let response = reqwest::Client::new()
         .get("https://data-api.binance.vision/api/v3/exchangeInfo")
+        .query(query)
         .send()
         .await?
-        .json::<ExchangeInfo>()
-        .await?;
-
-    let intervals_map = HashMap::from([
-        (String::from("SECOND"), Duration::from_secs(1)),
-        (String::from("MINUTE"), Duration::from_secs(60)),
-        (String::from("HOUR"), Duration::from_secs(60 * 60)),
-        (String::from("DAY"), Duration::from_secs(60 * 60 * 24)),
-    ]);
-    let mut tbs = HashMap::<_, _>::from_iter(
-        exchange_info.rateLimits.into_iter().map(|rl| (
-            rl.rateLimitType,
-            TokenBucket::new(rl.limit, rl.intervalNum * intervals_map[&rl.interval])
-        ))
-    );
-
-    Ok((
-        Arc::new(tbs.remove("RAW_REQUESTS").unwrap()),
-        Arc::new(tbs.remove("REQUEST_WEIGHT").unwrap()),
-    ))
+        .error_for_status()?;
+    let (status, headers) = (response.status(), response.headers().clone());
+    let exchange_info = response.json::<ExchangeInfo<T>>().await?;
+
+    let mut rl = RateLimiter::new(rate_limit_backend);
+
+    for limit in exchange_info.rateLimits {
+        let kind = match limit.rateLimitType.as_str() {
+            "RAW_REQUESTS" => RateLimitType::RawRequests,
+            "REQUEST_WEIGHT" => RateLimitType::RequestWeight,
+            "ORDERS" => RateLimitType::Orders,
+            _ => continue,
+        };
+        let interval = match limit.interval.as_str() {
+            "SECOND" => Duration::from_secs(1),
+            "MINUTE" => Duration::from_secs(60),
+            "HOUR" => Duration::from_secs(60 * 60),
+            "DAY" => Duration::from_secs(60 * 60 * 24),
+            _ => continue,
+        };
+        let letter = limit.interval.chars().next().unwrap();
+
+        rl.add_limit(kind, limit.intervalNum, letter, limit.limit, limit.intervalNum * interval);
+    }
+    rl.acquire(EXCHANGE_INFO_WEIGHT).await;
+    rl.update(prefix, status, &headers).await;
+
+    Ok((exchange_info.info, Arc::new(rl)))
+}
+
+pub(super) async fn get_rate_limiter(config: &SystemConfig) -> reqwest::Result<Arc<RateLimiter>> {
+    let (IgnoredAny, rl) =
+        get_exchange_info(&config.log_prefix, Arc::clone(&config.rate_limit_backend), &json!({})).await?;
+
+    Ok(rl)
 }
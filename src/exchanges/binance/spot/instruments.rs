@@ -0,0 +1,174 @@
+use super::info::get_exchange_info;
+use crate::rate_limit::RateLimitBackend;
+use crate::{Instrument, Pair};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct Symbols {
+    symbols: Vec<Symbol>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct Symbol {
+    status: String,
+    baseAsset: String,
+    baseAssetPrecision: u32,
+    quoteAsset: String,
+    quoteAssetPrecision: u32,
+    orderTypes: Vec<String>,
+    ocoAllowed: bool,
+    filters: Vec<Filter>,
+    #[serde(default)]
+    permissions: Vec<String>,
+    #[serde(default)]
+    permissionSets: Vec<Vec<String>>,
+}
+
+/// <https://developers.binance.com/docs/binance-spot-api-docs/filters>
+#[derive(Debug, Deserialize)]
+#[serde(tag = "filterType")]
+#[allow(non_snake_case)]
+enum Filter {
+    #[serde(rename = "PRICE_FILTER")]
+    Price {
+        minPrice: Decimal,
+        maxPrice: Decimal,
+        tickSize: Decimal,
+    },
+    #[serde(rename = "LOT_SIZE")]
+    LotSize {
+        minQty: Decimal,
+        maxQty: Decimal,
+        stepSize: Decimal,
+    },
+    #[serde(rename = "MIN_NOTIONAL")]
+    MinNotional {
+        minNotional: Decimal,
+    },
+    #[serde(rename = "NOTIONAL")]
+    Notional {
+        minNotional: Decimal,
+        maxNotional: Decimal,
+    },
+    #[serde(other)]
+    Other,
+}
+
+impl From<Symbol> for Instrument {
+    fn from(symbol: Symbol) -> Self {
+        let mut instrument = Self {
+            pair: Pair::new(
+                symbol.baseAsset.to_lowercase(),
+                symbol.quoteAsset.to_lowercase(),
+            ),
+            status: symbol.status,
+            base_precision: symbol.baseAssetPrecision,
+            quote_precision: symbol.quoteAssetPrecision,
+            min_price: Decimal::ZERO,
+            max_price: Decimal::ZERO,
+            tick_size: Decimal::ZERO,
+            min_qty: Decimal::ZERO,
+            max_qty: Decimal::ZERO,
+            step_size: Decimal::ZERO,
+            min_notional: None,
+            max_notional: None,
+            // Binance moved permissions to `permissionSets`, `permissions` is left empty.
+            permissions: if symbol.permissions.is_empty() {
+                let mut permissions = symbol.permissionSets.concat();
+                permissions.sort();
+                permissions.dedup();
+                permissions
+            } else {
+                symbol.permissions
+            },
+            order_types: symbol.orderTypes,
+            oco_allowed: symbol.ocoAllowed,
+        };
+
+        for filter in symbol.filters {
+            match filter {
+                Filter::Price { minPrice, maxPrice, tickSize } => {
+                    instrument.min_price = minPrice;
+                    instrument.max_price = maxPrice;
+                    instrument.tick_size = tickSize;
+                }
+                Filter::LotSize { minQty, maxQty, stepSize } => {
+                    instrument.min_qty = minQty;
+                    instrument.max_qty = maxQty;
+                    instrument.step_size = stepSize;
+                }
+                Filter::MinNotional { minNotional } => {
+                    instrument.min_notional = Some(minNotional);
+                }
+                Filter::Notional { minNotional, maxNotional } => {
+                    instrument.min_notional = Some(minNotional);
+                    instrument.max_notional = Some(maxNotional);
+                }
+                Filter::Other => {}
+            }
+        }
+
+        instrument
+    }
+}
+
+/// Charges the request to `rate_limit_backend` and reconciles usage reported by the response with it,
+/// `log_prefix` and `rate_limit_backend` should be the ones of [`Config::log_prefix`](crate::Config::log_prefix)
+/// and [`Config::rate_limit_backend`](crate::Config::rate_limit_backend).
+///
+/// <https://developers.binance.com/docs/binance-spot-api-docs/rest-api/general-endpoints#exchange-information>
+pub async fn get_instruments(
+    log_prefix: &str,
+    rate_limit_backend: Arc<dyn RateLimitBackend>,
+) -> reqwest::Result<Vec<Instrument>> {
+    let query = json!({
+        "permissions": "SPOT",
+    });
+    let (Symbols { symbols }, _) = get_exchange_info::<Symbols>(log_prefix, rate_limit_backend, &query).await?;
+
+    Ok(symbols
+        .into_iter()
+        .map(Instrument::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn symbol() {
+        let symbol = serde_json::from_str::<Symbol>(r#"{
+            "symbol": "BTCUSDT",
+            "status": "TRADING",
+            "baseAsset": "BTC",
+            "baseAssetPrecision": 8,
+            "quoteAsset": "USDT",
+            "quotePrecision": 8,
+            "quoteAssetPrecision": 8,
+            "orderTypes": ["LIMIT", "LIMIT_MAKER", "MARKET"],
+            "ocoAllowed": true,
+            "filters": [
+                {"filterType": "PRICE_FILTER", "minPrice": "0.01000000", "maxPrice": "1000000.00000000", "tickSize": "0.01000000"},
+                {"filterType": "LOT_SIZE", "minQty": "0.00001000", "maxQty": "9000.00000000", "stepSize": "0.00001000"},
+                {"filterType": "ICEBERG_PARTS", "limit": 10},
+                {"filterType": "NOTIONAL", "minNotional": "5.00000000", "applyMinToMarket": true, "maxNotional": "9000000.00000000", "applyMaxToMarket": false, "avgPriceMins": 5}
+            ],
+            "permissions": [],
+            "permissionSets": [["SPOT", "MARGIN"], ["TRD_GRP_004"]]
+        }"#).unwrap();
+        let instrument = Instrument::from(symbol);
+
+        assert_eq!(instrument.pair, Pair::new(String::from("btc"), String::from("usdt")));
+        assert_eq!(instrument.tick_size, dec!(0.01));
+        assert_eq!(instrument.step_size, dec!(0.00001));
+        assert_eq!(instrument.min_notional, Some(dec!(5)));
+        assert_eq!(instrument.permissions, vec!["MARGIN", "SPOT", "TRD_GRP_004"]);
+    }
+}
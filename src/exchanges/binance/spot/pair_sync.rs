@@ -0,0 +1,429 @@
+use super::audit::{self, PairAudit};
+use super::decode::EventPayload;
+use super::{pair_labels, Received};
+use super::snapshot::Snapshot;
+use crate::health::PairHealth;
+use crate::metrics::{self, Counter, Gauge, Histogram};
+use crate::{BookWriter, ClockSync, InvariantPolicy, LatencyRecorder, Pair, SharedBook, SystemConfig};
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+/// Returns latency from event time to `received`, corrected by the exchange clock offset.
+fn check_latency(
+    config: &SystemConfig,
+    pair: &Pair,
+    event: &EventPayload,
+    received: SystemTime,
+    clock: &ClockSync,
+    histogram: &Histogram,
+) -> Duration {
+    let event_time = UNIX_EPOCH + Duration::from_millis(event.E);
+
+    let latency = match clock.to_exchange(received).duration_since(event_time) {
+        Ok(latency) => latency,
+        Err(err) => {
+            // Event is ahead of the receive time, which is expected within the offset uncertainty.
+            let uncertainty = clock.offset().map(|offset| offset.uncertainty).unwrap_or_default();
+
+            if err.duration() > config.max_latency_error + uncertainty {
+                log_event!(
+                    warn, format_args!("{} [{pair}]:", config.log_prefix),
+                    { %pair, ahead = ?err.duration(), ?uncertainty },
+                    "latency error - {:?} ahead", err.duration(),
+                );
+            }
+            Duration::ZERO
+        }
+    };
+    histogram.observe(latency);
+
+    latency
+}
+
+fn apply_event(writer: &mut BookWriter, event: EventPayload) {
+    let EventPayload { u, b: bids, a: asks, .. } = event;
+
+    writer.update(move |book| {
+        book.update_id = u;
+        for &order in &bids {
+            book.bids.diff_update(order)
+        }
+        for &order in &asks {
+            book.asks.diff_update(order)
+        }
+    });
+}
+
+struct PairMetrics {
+    events_applied: Counter,
+    snapshot_gaps: Counter,
+    sequence_gaps: Counter,
+    inactivity: Counter,
+    audit_divergences: Counter,
+    invariant_violations: Counter,
+    shallow_books: Counter,
+    buffer_overflows: Counter,
+    latency: Histogram,
+    bids_depth: Gauge,
+    asks_depth: Gauge,
+    bids_valid_depth: Gauge,
+    asks_valid_depth: Gauge,
+}
+
+impl PairMetrics {
+    fn new(pair: &Pair) -> Self {
+        let labels = pair_labels(pair);
+        let with = |key, value: &str| {
+            let mut labels = labels.clone();
+            labels.push((key, String::from(value)));
+            labels
+        };
+
+        Self {
+            events_applied: metrics::counter("market_view_events_applied_total", labels.clone()),
+            snapshot_gaps: metrics::counter("market_view_resyncs_total", with("reason", "snapshot_gap")),
+            sequence_gaps: metrics::counter("market_view_resyncs_total", with("reason", "sequence_gap")),
+            inactivity: metrics::counter("market_view_resyncs_total", with("reason", "inactivity")),
+            audit_divergences: metrics::counter("market_view_resyncs_total", with("reason", "audit")),
+            invariant_violations: metrics::counter("market_view_resyncs_total", with("reason", "invariant")),
+            shallow_books: metrics::counter("market_view_resyncs_total", with("reason", "valid_depth")),
+            buffer_overflows: metrics::counter("market_view_snapshot_buffer_overflows_total", labels.clone()),
+            latency: metrics::histogram("market_view_event_latency_seconds", labels.clone()),
+            bids_depth: metrics::gauge("market_view_book_depth", with("side", "bids")),
+            asks_depth: metrics::gauge("market_view_book_depth", with("side", "asks")),
+            bids_valid_depth: metrics::gauge("market_view_book_valid_depth", with("side", "bids")),
+            asks_valid_depth: metrics::gauge("market_view_book_valid_depth", with("side", "asks")),
+        }
+    }
+}
+
+enum State {
+    /// No events yet. Snapshot is requested after the first one in case websocket server
+    /// will start sending events too late (later than we get snapshot).
+    Idle,
+    /// Snapshot is requested, events are buffered meanwhile.
+    Snapshotting { buffer: VecDeque<(Received, EventPayload)> },
+    /// Snapshot is applied, waiting for the event which follows it.
+    Syncing { last_update_id: u64 },
+    /// Events are applied in order, duplicates and overlaps from two connections
+    /// while one replaces the other are resolved by update ids.
+    Live { prev_u: u64 },
+}
+
+/// Keeps local book of a pair in sync, independently of how events and snapshots are delivered.
+///
+/// <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#how-to-manage-a-local-order-book-correctly>
+///
+/// ### Snapshot and Event Flow
+///
+/// ```text
+///    U-------u
+///    | Event |
+///    +-------+
+///            U-------u
+///            | Event |
+///            +-------+
+///              U--------------u
+///              |    Event     |
+///              +--------------+
+///                U----------u
+///                | Snapshot |
+///                +----------+
+///                   U-----u
+///                   |Event|
+///                   +-----+
+///                       U-------u
+///                       | Event |
+///                       +-------+
+///                               U-------u
+///                               | Event |
+///                               +-------+
+/// ```
+pub(super) struct PairSync {
+    pair: Pair,
+    writer: BookWriter,
+    state: State,
+    metrics: PairMetrics,
+    latency: LatencyRecorder,
+    health: PairHealth,
+    audit: PairAudit,
+    // Depth of requested snapshots.
+    snapshot_limit: usize,
+    // Complete bids and asks required, lowered by shallow snapshots.
+    min_valid_depth: (usize, usize),
+    // Book broke its invariants, so it's checked in full until an update fixes it.
+    broken: bool,
+    timeout: Duration,
+    last_event: Instant,
+}
+
+impl PairSync {
+    pub(super) fn new(
+        config: &SystemConfig,
+        pair: Pair,
+        book: &SharedBook,
+        snapshot_limit: usize,
+        latency: LatencyRecorder,
+        health: PairHealth,
+        audit: PairAudit,
+    ) -> Self {
+        let metrics = PairMetrics::new(&pair);
+        let timeout = config.pair_timeout(&pair);
+        // Sync of a restarted task starts over.
+        health.set_live(false);
+
+        Self {
+            pair, writer: book.writer(), state: State::Idle, metrics, latency, health, audit, snapshot_limit,
+            min_valid_depth: (0, 0), broken: false, timeout, last_event: Instant::now(),
+        }
+    }
+
+    pub(super) fn pair(&self) -> &Pair {
+        &self.pair
+    }
+
+    pub(super) fn snapshot_limit(&self) -> usize {
+        self.snapshot_limit
+    }
+
+    fn update_depth(&self) {
+        let book = self.writer.book();
+
+        self.metrics.bids_depth.set(book.bids().len() as u64);
+        self.metrics.asks_depth.set(book.asks().len() as u64);
+        self.metrics.bids_valid_depth.set(book.bids().valid_len() as u64);
+        self.metrics.asks_valid_depth.set(book.asks().valid_len() as u64);
+    }
+
+    /// Resyncs the book once its capacity dropped so many levels
+    /// that fewer than required are known to be complete.
+    /// Returns whether new snapshot should be requested.
+    fn check_valid_depth(&mut self, config: &SystemConfig) -> bool {
+        let book = self.writer.book();
+        let (bids, asks) = (book.bids().valid_len(), book.asks().valid_len());
+
+        if bids >= self.min_valid_depth.0 && asks >= self.min_valid_depth.1 {
+            return false;
+        }
+        let pair = &self.pair;
+        log_event!(
+            warn, format_args!("{} [{pair}]:", config.log_prefix), { %pair, bids, asks, reason = "valid_depth" },
+            "only {bids} bids and {asks} asks are complete, resyncing",
+        );
+
+        self.metrics.shallow_books.inc();
+        self.state = State::Snapshotting { buffer: VecDeque::new() };
+        self.health.set_live(false);
+        true
+    }
+
+    /// Checks the book after an update of `touched` bid and ask prices, or in full,
+    /// and follows [`InvariantPolicy`] if it's broken.
+    /// Returns whether the book can be served.
+    fn check_book(&mut self, config: &SystemConfig, touched: Option<(&[Decimal], &[Decimal])>) -> bool {
+        let book = self.writer.book();
+        let checked = match touched {
+            Some((bids, asks)) if !self.broken => book.check_near(bids, asks),
+            _ => book.check(),
+        };
+        self.broken = checked.is_err();
+        let Err(violation) = checked else {
+            return true;
+        };
+        let pair = &self.pair;
+        log_event!(
+            error, format_args!("{} [{pair}]:", config.log_prefix),
+            { %pair, ?violation, policy = ?config.invariant_policy },
+            "book invariant broken: {violation:?}",
+        );
+
+        let mut labels = pair_labels(pair);
+        labels.push(("invariant", String::from(violation.name())));
+        metrics::counter("market_view_invariant_violations_total", labels).inc();
+
+        match config.invariant_policy {
+            InvariantPolicy::Log => true,
+            InvariantPolicy::MarkStale => false,
+            InvariantPolicy::Resync => {
+                self.metrics.invariant_violations.inc();
+                self.state = State::Snapshotting { buffer: VecDeque::new() };
+                false
+            }
+        }
+    }
+
+    /// Returns whether new snapshot should be requested.
+    fn apply(&mut self, config: &SystemConfig, event: EventPayload, received: Received, replayed: bool) -> bool {
+        let exchange_to_receive =
+            check_latency(config, &self.pair, &event, received.time, self.latency.clock(), &self.metrics.latency);
+        let (first, last, recording) = (event.U, event.u, self.audit.recording());
+        let touched = audit::touched(&event);
+        let (touched_bids, touched_asks) = touched.split_at(event.b.len());
+        apply_event(&mut self.writer, event);
+        if recording {
+            self.audit.record(first, last, touched.clone(), self.writer.book());
+        }
+        let valid = self.check_book(config, Some((touched_bids, touched_asks)));
+        // Book may also be marked stale while its connection is down.
+        self.health.set_live(valid);
+        // Replayed events waited for the snapshot, which isn't processing time.
+        let receive_to_apply = (!replayed).then(|| received.at.elapsed());
+
+        self.latency.record(exchange_to_receive, receive_to_apply);
+        self.metrics.events_applied.inc();
+        self.update_depth();
+
+        if !valid && config.invariant_policy == InvariantPolicy::Resync {
+            return true;
+        }
+        self.check_valid_depth(config)
+    }
+
+    /// Returns whether new snapshot should be requested.
+    pub(super) fn on_event(&mut self, config: &SystemConfig, event: EventPayload, received: Received) -> bool {
+        self.health.on_event(received.time);
+        self.last_event = Instant::now();
+
+        if self.audit.take_diverged() && matches!(self.state, State::Syncing { .. } | State::Live { .. }) {
+            // Auditor has logged the difference.
+            self.metrics.audit_divergences.inc();
+            self.state = State::Snapshotting { buffer: VecDeque::from([(received, event)]) };
+            self.health.set_live(false);
+            return true;
+        }
+        self.handle(config, event, received, false)
+    }
+
+    fn handle(&mut self, config: &SystemConfig, event: EventPayload, received: Received, replayed: bool) -> bool {
+        let pair = &self.pair;
+
+        match &mut self.state {
+            State::Idle => {
+                self.state = State::Snapshotting { buffer: VecDeque::from([(received, event)]) };
+                true
+            }
+            State::Snapshotting { buffer } => {
+                if buffer.len() >= config.max_buffered_events {
+                    // Snapshot takes too long, the pair restarts from a new one.
+                    log_event!(
+                        warn, format_args!("{} [{pair}]:", config.log_prefix), { %pair, dropped = buffer.len() },
+                        "dropping {} events buffered during snapshot, resyncing", buffer.len(),
+                    );
+                    buffer.clear();
+                    buffer.push_back((received, event));
+                    self.metrics.buffer_overflows.inc();
+                    return true;
+                }
+                buffer.push_back((received, event));
+                false
+            }
+            &mut State::Syncing { last_update_id } => {
+                if event.u <= last_update_id {
+                    // Snapshot covers this event.
+                    return false;
+                }
+                if event.U > last_update_id + 1 {
+                    // We missed some event.
+                    log_event!(
+                        error, format_args!("{} [{pair}]:", config.log_prefix),
+                        { %pair, U = event.U, last_update_id, reason = "snapshot_gap" },
+                        "U ({}) > lastUpdateId ({last_update_id}) + 1, resyncing", event.U,
+                    );
+                    self.metrics.snapshot_gaps.inc();
+                    self.state = State::Snapshotting { buffer: VecDeque::new() };
+                    self.health.set_live(false);
+                    return true;
+                }
+                self.state = State::Live { prev_u: event.u };
+
+                self.apply(config, event, received, replayed)
+            }
+            State::Live { prev_u } => {
+                if event.u <= *prev_u {
+                    // Already applied, delivered by the other connection during rotation.
+                    return false;
+                }
+                // Event overlapping the applied ones comes from the other connection,
+                // it carries final levels of the whole range, so it applies like after snapshot.
+                if event.U > *prev_u + 1 {
+                    // We missed some event.
+                    log_event!(
+                        error, format_args!("{} [{pair}]:", config.log_prefix),
+                        { %pair, U = event.U, prev_u, reason = "sequence_gap" },
+                        "U ({}) > prev_u ({prev_u}) + 1, resyncing", event.U,
+                    );
+                    self.metrics.sequence_gaps.inc();
+                    self.state = State::Snapshotting { buffer: VecDeque::new() };
+                    self.health.set_live(false);
+                    return true;
+                }
+                *prev_u = event.u;
+
+                self.apply(config, event, received, replayed)
+            }
+        }
+    }
+
+    /// Resyncs live pair without events for longer than its timeout, as its stream may be stuck.
+    /// Returns whether new snapshot should be requested.
+    pub(super) fn check_inactive(&mut self, config: &SystemConfig, now: Instant) -> bool {
+        if !matches!(self.state, State::Live { .. }) || now - self.last_event < self.timeout {
+            return false;
+        }
+        let pair = &self.pair;
+        log_event!(
+            warn, format_args!("{} [{pair}]:", config.log_prefix),
+            { %pair, timeout = ?self.timeout, reason = "inactivity" },
+            "no events for {:?}, resyncing", self.timeout,
+        );
+
+        self.metrics.inactivity.inc();
+        self.state = State::Snapshotting { buffer: VecDeque::new() };
+        self.health.set_live(false);
+        true
+    }
+
+    /// Returns whether new snapshot should be requested.
+    pub(super) fn on_snapshot(&mut self, config: &SystemConfig, snapshot: Snapshot) -> bool {
+        let State::Snapshotting { buffer } = &mut self.state else {
+            return false;
+        };
+        let buffer = std::mem::take(buffer);
+
+        let bids = snapshot.bids;
+        let asks = snapshot.asks;
+        let (id, limit) = (snapshot.lastUpdateId, self.snapshot_limit);
+        self.writer.update(move |book| {
+            book.update_id = id;
+            book.bids.shot_update(&bids, limit);
+            book.asks.shot_update(&asks, limit);
+        });
+        self.state = State::Syncing { last_update_id: id };
+        self.update_depth();
+        let book = self.writer.book();
+        self.min_valid_depth = (
+            book.bids().valid_len().min(config.min_valid_depth),
+            book.asks().valid_len().min(config.min_valid_depth),
+        );
+        if !self.check_book(config, None) {
+            if config.invariant_policy == InvariantPolicy::Resync {
+                self.state = State::Snapshotting { buffer };
+                return true;
+            }
+            // Stays stale until an update fixes the book.
+            self.health.set_live(false);
+        }
+        if self.audit.recording() {
+            self.audit.record(id, id, Vec::new(), self.writer.book());
+        }
+
+        let mut resync = false;
+        for (received, event) in buffer {
+            // Events following a gap are buffered again for the next snapshot.
+            resync |= self.handle(config, event, received, true);
+        }
+        resync
+    }
+}
@@ -1,10 +1,13 @@
+use super::info::get_exchange_info;
+use crate::rate_limit::RateLimitBackend;
 use crate::Pair;
 use serde::Deserialize;
 use serde_json::json;
+use std::sync::Arc;
 
 #[derive(Debug, Deserialize)]
 #[allow(non_snake_case)]
-struct ExchangeInfo {
+struct Symbols {
     symbols: Vec<Symbol>,
 }
 
@@ -15,20 +18,22 @@ struct Symbol {
     quoteAsset: String,
 }
 
+/// Charges the request to `rate_limit_backend` and reconciles usage reported by the response with it,
+/// `log_prefix` and `rate_limit_backend` should be the ones of [`Config::log_prefix`](crate::Config::log_prefix)
+/// and [`Config::rate_limit_backend`](crate::Config::rate_limit_backend).
+///
 /// <https://developers.binance.com/docs/binance-spot-api-docs/rest-api/general-endpoints#exchange-information>
-pub async fn get_pairs() -> reqwest::Result<Vec<Pair>> {
-    let exchange_info = reqwest::Client::new()
-        .get("https://data-api.binance.vision/api/v3/exchangeInfo")
-        .query(&json!({
-            "permissions": "SPOT",
-            "symbolStatus": "TRADING",
-        }))
-        .send()
-        .await?
-        .json::<ExchangeInfo>()
-        .await?;
+pub async fn get_pairs(
+    log_prefix: &str,
+    rate_limit_backend: Arc<dyn RateLimitBackend>,
+) -> reqwest::Result<Vec<Pair>> {
+    let query = json!({
+        "permissions": "SPOT",
+        "symbolStatus": "TRADING",
+    });
+    let (Symbols { symbols }, _) = get_exchange_info::<Symbols>(log_prefix, rate_limit_backend, &query).await?;
 
-    Ok(exchange_info.symbols
+    Ok(symbols
         .into_iter()
         .map(|s| Pair::new(
             s.baseAsset.to_lowercase(),
@@ -0,0 +1,100 @@
+use super::snapshot::Snapshot;
+use super::pair_labels;
+use crate::health::PairHealth;
+use crate::metrics::{self, Counter, Gauge};
+use crate::{BookWriter, Pair, SharedBook};
+use std::time::SystemTime;
+
+/// Keeps book of a pair from its partial depth stream.
+///
+/// Every event carries top levels of the book and replaces it,
+/// so the book needs no snapshots and can't get out of sync.
+///
+/// <https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#partial-book-depth-streams>
+pub(super) struct PartialSync {
+    writer: BookWriter,
+    // Levels of every event.
+    depth: usize,
+    health: PairHealth,
+    events_applied: Counter,
+    bids_depth: Gauge,
+    asks_depth: Gauge,
+}
+
+impl PartialSync {
+    pub(super) fn new(pair: &Pair, book: &SharedBook, depth: usize, health: PairHealth) -> Self {
+        let labels = pair_labels(pair);
+        let side = |side: &str| {
+            let mut labels = labels.clone();
+            labels.push(("side", String::from(side)));
+            labels
+        };
+
+        Self {
+            writer: book.writer(),
+            depth,
+            health,
+            events_applied: metrics::counter("market_view_events_applied_total", labels.clone()),
+            bids_depth: metrics::gauge("market_view_book_depth", side("bids")),
+            asks_depth: metrics::gauge("market_view_book_depth", side("asks")),
+        }
+    }
+
+    pub(super) fn on_event(&mut self, event: Snapshot, received: SystemTime) {
+        self.health.on_event(received);
+
+        let book = self.writer.book();
+        if event.lastUpdateId <= book.update_id() {
+            // Already applied, delivered by the other connection during rotation.
+            return;
+        }
+        let Snapshot { lastUpdateId: id, mut bids, mut asks } = event;
+        let limit = usize::min(self.depth, book.capacity());
+        bids.truncate(limit);
+        asks.truncate(limit);
+
+        self.writer.update(move |book| {
+            book.update_id = id;
+            book.bids.shot_update(&bids, limit);
+            book.asks.shot_update(&asks, limit);
+        });
+        // Book may be marked stale while its connection is down.
+        self.health.set_live(true);
+        self.events_applied.inc();
+
+        let book = self.writer.book();
+        self.bids_depth.set(book.bids().len() as u64);
+        self.asks_depth.set(book.asks().len() as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::order;
+    use crate::{Book, Order, SideKind};
+
+    #[test]
+    fn replace_book() {
+        let pair = Pair::new(String::from("partial"), String::from("usdt"));
+        let book = SharedBook::new(Book::new(2, SideKind::Vec));
+        let mut sync = PartialSync::new(&pair, &book, 5, PairHealth::default());
+        let event = |id, bids: &[Order], asks: &[Order]| Snapshot {
+            lastUpdateId: id, bids: bids.to_vec(), asks: asks.to_vec(),
+        };
+
+        sync.on_event(event(5, &[order(3, 1), order(2, 1), order(1, 1)], &[order(4, 1)]), SystemTime::now());
+        assert_eq!(book.load().bids().to_vec(), [order(3, 1), order(2, 1)]);
+        assert_eq!(book.load().asks().to_vec(), [order(4, 1)]);
+
+        // Copy of an applied event from the other connection.
+        sync.on_event(event(5, &[], &[]), SystemTime::now());
+        assert_eq!(book.load().update_id(), 5);
+        assert_eq!(book.load().bids().len(), 2);
+
+        sync.on_event(event(7, &[order(2, 1)], &[order(3, 1), order(5, 1)]), SystemTime::now());
+        assert_eq!(book.load().update_id(), 7);
+        assert_eq!(book.load().bids().to_vec(), [order(2, 1)]);
+        assert_eq!(book.load().asks().to_vec(), [order(3, 1), order(5, 1)]);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::rate_limit::RateLimitBackend;
+use crate::TokenBucket;
+use http::{HeaderMap, StatusCode};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum RateLimitType {
+    RawRequests,
+    RequestWeight,
+    Orders,
+}
+
+struct Bucket {
+    kind: RateLimitType,
+    // Response header which reports usage of this limit, lowercase.
+    header: Option<String>,
+    cap: usize,
+    interval: Duration,
+    // Usage reported by the last response and when it was received.
+    used: Mutex<(usize, Instant)>,
+    tb: TokenBucket,
+}
+
+/// REST limits of a single IP.
+///
+/// <https://developers.binance.com/docs/binance-spot-api-docs/rest-api/limits>
+pub(super) struct RateLimiter {
+    backend: Arc<dyn RateLimitBackend>,
+    buckets: Vec<Bucket>,
+    paused_until: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub(super) fn new(backend: Arc<dyn RateLimitBackend>) -> Self {
+        Self { backend, buckets: Vec::new(), paused_until: Mutex::new(None) }
+    }
+
+    /// `interval_letter` is the first letter of the limit interval (`S`, `M`, `H` or `D`)
+    /// as it appears in the usage header name.
+    pub(super) fn add_limit(
+        &mut self,
+        kind: RateLimitType,
+        interval_num: u32,
+        interval_letter: char,
+        limit: usize,
+        interval: Duration,
+    ) {
+        let header = match kind {
+            RateLimitType::RawRequests => None,
+            RateLimitType::RequestWeight => Some("x-mbx-used-weight"),
+            RateLimitType::Orders => Some("x-mbx-order-count"),
+        }.map(|h| format!("{h}-{interval_num}{}", interval_letter.to_ascii_lowercase()));
+        // Limits are shared by all Binance spot APIs of the same IP.
+        let key = format!("binance-spot-{kind:?}-{interval_num}{interval_letter}");
+        let tb = TokenBucket::new(key, limit, interval, Arc::clone(&self.backend));
+
+        let used = Mutex::new((0, Instant::now()));
+        self.buckets.push(Bucket { kind, header, cap: limit, interval, used, tb });
+    }
+
+    /// Waits until a request of given `weight` can be sent.
+    pub(super) async fn acquire(&self, weight: u32) {
+        self.wait_pause().await;
+
+        for bucket in &self.buckets {
+            match bucket.kind {
+                RateLimitType::RawRequests => bucket.tb.acquire(1).await,
+                RateLimitType::RequestWeight => bucket.tb.acquire(weight).await,
+                // We don't place orders.
+                RateLimitType::Orders => {}
+            }
+        }
+    }
+
+    /// Reconciles local state with limits usage reported in response headers
+    /// and pauses all requests if we were rate limited or banned.
+    pub(super) async fn update(&self, prefix: &str, status: StatusCode, headers: &HeaderMap) {
+        for bucket in &self.buckets {
+            let Some(header) = &bucket.header else { continue };
+
+            let used = headers.get(header)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<usize>().ok());
+            if let Some(used) = used {
+                *bucket.used.lock().unwrap() = (used, Instant::now());
+                bucket.tb.sync(used).await;
+            }
+        }
+
+        if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::IM_A_TEAPOT {
+            let retry_after = headers.get(http::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok())
+                .map_or(DEFAULT_RETRY_AFTER, Duration::from_secs);
+            log_event!(
+                warn, prefix, { %status, ?retry_after },
+                "rate limited ({status}), pausing requests for {retry_after:?}",
+            );
+
+            let until = Instant::now() + retry_after;
+            let mut paused_until = self.paused_until.lock().unwrap();
+            if paused_until.is_none_or(|t| t < until) {
+                *paused_until = Some(until);
+            }
+        }
+    }
+
+    /// Smallest unused fraction of reported limits, zero while requests are paused.
+    ///
+    /// Reported usage is refilled since the response like the token bucket,
+    /// so headroom recovers while no requests are sent.
+    pub(super) fn headroom(&self) -> f64 {
+        let now = Instant::now();
+        if self.paused_until.lock().unwrap().is_some_and(|until| until > now) {
+            return 0.0;
+        }
+
+        self.buckets.iter()
+            .filter(|b| b.header.is_some() && b.kind != RateLimitType::Orders)
+            .map(|b| {
+                let (used, at) = *b.used.lock().unwrap();
+                let refilled = b.cap as f64 * (now - at).as_secs_f64() / b.interval.as_secs_f64();
+                1.0 - ((used as f64 - refilled).max(0.0) / b.cap as f64).min(1.0)
+            })
+            .fold(1.0, f64::min)
+    }
+
+    async fn wait_pause(&self) {
+        loop {
+            let paused_until = *self.paused_until.lock().unwrap();
+
+            match paused_until {
+                Some(until) if until > Instant::now() => tokio::time::sleep_until(until).await,
+                _ => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::LocalBackend;
+    use http::HeaderValue;
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limiter() {
+        let mut rl = RateLimiter::new(Arc::new(LocalBackend::new()));
+        rl.add_limit(RateLimitType::RequestWeight, 1, 'M', 6000, Duration::from_secs(60));
+        rl.add_limit(RateLimitType::RawRequests, 5, 'M', 61000, Duration::from_secs(300));
+        let start = Instant::now();
+
+        rl.acquire(250).await;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-mbx-used-weight-1m", HeaderValue::from_static("5900"));
+        rl.update("", StatusCode::OK, &headers).await;
+        assert!((rl.headroom() - 100.0 / 6000.0).abs() < 1e-9);
+        // 100 weight left, 100 more are refilled each second.
+        rl.acquire(300).await;
+        assert_eq!(start.elapsed().as_secs(), 2);
+        // Reported usage is refilled as well until the next response.
+        tokio::time::advance(Duration::from_secs(3)).await;
+        assert!((rl.headroom() - 600.0 / 6000.0).abs() < 1e-3);
+
+        headers.insert("retry-after", HeaderValue::from_static("30"));
+        rl.update("", StatusCode::TOO_MANY_REQUESTS, &headers).await;
+        assert_eq!(rl.headroom(), 0.0);
+        rl.acquire(5).await;
+        assert_eq!(start.elapsed().as_secs(), 35);
+    }
+}
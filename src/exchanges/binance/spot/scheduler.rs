@@ -0,0 +1,203 @@
+use super::rate_limiter::RateLimiter;
+use crate::Pair;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+struct Waiter {
+    id: u64,
+    priority: u64,
+    since: Instant,
+}
+
+struct Queue {
+    next_id: u64,
+    // Id of the waiter which is acquiring rate limits right now.
+    dispatching: Option<u64>,
+    waiters: Vec<Waiter>,
+}
+
+/// Orders snapshot requests of a Place, so important pairs
+/// don't wait for rate limits behind obscure ones.
+///
+/// Requests are served by priority (higher first, FIFO among equal),
+/// but a request waiting longer than `max_wait` goes before any fresher one.
+pub(super) struct SnapshotScheduler {
+    rl: Arc<RateLimiter>,
+    priorities: HashMap<Pair, u64>,
+    // Pairs with highest priorities, which get snapshots of full book capacity.
+    full_limit_pairs: HashSet<Pair>,
+    // Depth of the rest, which get full snapshots too unless set.
+    reduced_limit: Option<usize>,
+    max_wait: Duration,
+    queue: Mutex<Queue>,
+    notify: Notify,
+}
+
+struct QueueGuard<'a> {
+    scheduler: &'a SnapshotScheduler,
+    id: u64,
+}
+
+impl SnapshotScheduler {
+    pub(super) fn new(
+        rl: Arc<RateLimiter>,
+        priorities: HashMap<Pair, u64>,
+        full_limit_pairs: usize,
+        reduced_limit: Option<usize>,
+        max_wait: Duration,
+    ) -> Self {
+        let mut ranked = Vec::from_iter(priorities.iter());
+        ranked.sort_by(|(_, a), (_, b)| b.cmp(a));
+        let full_limit_pairs = HashSet::from_iter(
+            ranked.into_iter()
+                .take(full_limit_pairs)
+                .map(|(pair, _)| pair.clone())
+        );
+
+        Self {
+            rl,
+            priorities,
+            full_limit_pairs,
+            reduced_limit,
+            max_wait,
+            queue: Mutex::new(Queue { next_id: 0, dispatching: None, waiters: Vec::new() }),
+            notify: Notify::new(),
+        }
+    }
+
+    pub(super) fn rate_limiter(&self) -> &RateLimiter {
+        &self.rl
+    }
+
+    /// Snapshot depth to request for `pair`, reduced for low priority pairs to save weight.
+    pub(super) fn limit(&self, pair: &Pair, book_cap: usize) -> usize {
+        match self.reduced_limit {
+            Some(reduced_limit) if !self.full_limit_pairs.contains(pair) => usize::min(book_cap, reduced_limit),
+            _ => book_cap,
+        }
+    }
+
+    /// Waits for the turn of `pair` and until snapshot of given `weight` can be requested.
+    pub(super) async fn acquire(&self, pair: &Pair, weight: u32) {
+        let guard = self.enqueue(self.priorities.get(pair).copied().unwrap_or(0));
+
+        loop {
+            let notified = self.notify.notified();
+            if self.try_dispatch(guard.id) {
+                break;
+            }
+            notified.await;
+        }
+
+        self.rl.acquire(weight).await;
+        // Dropping guard passes the turn.
+    }
+
+    fn enqueue(&self, priority: u64) -> QueueGuard<'_> {
+        let mut queue = self.queue.lock().unwrap();
+
+        let id = queue.next_id;
+        queue.next_id += 1;
+        queue.waiters.push(Waiter { id, priority, since: Instant::now() });
+
+        QueueGuard { scheduler: self, id }
+    }
+
+    fn try_dispatch(&self, id: u64) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+
+        if queue.dispatching.is_some() {
+            return false;
+        }
+
+        let now = Instant::now();
+        let starved = queue.waiters.iter()
+            .filter(|w| now.duration_since(w.since) >= self.max_wait)
+            .min_by_key(|w| w.id);
+        let next = starved.or_else(|| {
+            queue.waiters.iter().max_by(|a, b| {
+                a.priority.cmp(&b.priority).then(b.id.cmp(&a.id))
+            })
+        });
+
+        if next.is_some_and(|w| w.id == id) {
+            queue.dispatching = Some(id);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Drop for QueueGuard<'_> {
+    fn drop(&mut self) {
+        let mut queue = self.scheduler.queue.lock().unwrap();
+
+        queue.waiters.retain(|w| w.id != self.id);
+        if queue.dispatching == Some(self.id) {
+            queue.dispatching = None;
+        }
+        drop(queue);
+
+        self.scheduler.notify.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::LocalBackend;
+    use super::super::rate_limiter::RateLimitType;
+
+    fn pair(ba: &str) -> Pair {
+        Pair::new(String::from(ba), String::from("usdt"))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn scheduler() {
+        let mut rl = RateLimiter::new(Arc::new(LocalBackend::new()));
+        rl.add_limit(RateLimitType::RequestWeight, 1, 'S', 10, Duration::from_secs(1));
+        let scheduler = Arc::new(SnapshotScheduler::new(
+            Arc::new(rl),
+            HashMap::from([(pair("btc"), 1000), (pair("eth"), 500), (pair("pepe"), 1)]),
+            2,
+            Some(100),
+            Duration::from_secs(2),
+        ));
+
+        assert_eq!(scheduler.limit(&pair("btc"), 5000), 5000);
+        assert_eq!(scheduler.limit(&pair("pepe"), 5000), 100);
+        let full = SnapshotScheduler::new(
+            Arc::clone(&scheduler.rl), HashMap::new(), 0, None, Duration::from_secs(2),
+        );
+        assert_eq!(full.limit(&pair("pepe"), 5000), 5000);
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        // Drain the bucket, so following requests queue up.
+        scheduler.acquire(&pair("pepe"), 10).await;
+        for p in ["pepe", "pepe", "eth", "btc"] {
+            tokio::spawn({
+                let scheduler = Arc::clone(&scheduler);
+                let tx = tx.clone();
+
+                async move {
+                    scheduler.acquire(&pair(p), 10).await;
+                    tx.send(p).unwrap();
+                }
+            });
+            tokio::task::yield_now().await;
+        }
+        drop(tx);
+
+        let mut order = Vec::new();
+        while let Some(p) = rx.recv().await {
+            order.push(p);
+        }
+        // First pepe was already dispatched,
+        // second one starves for 2 seconds and goes ahead of eth.
+        assert_eq!(order, vec!["pepe", "btc", "pepe", "eth"]);
+    }
+}
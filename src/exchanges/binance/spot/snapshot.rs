@@ -1,23 +1,25 @@
-use super::Update;
-use crate::{Pair, TokenBucket};
+use super::{decode, pair_labels, place_labels, scheduler::SnapshotScheduler};
+use crate::metrics;
+use crate::{Order, Pair, SystemConfig};
 use serde::Deserialize;
 use serde_json::json;
-use std::sync::Arc;
 
 #[derive(Debug, Deserialize)]
 #[allow(non_snake_case)]
-pub(super) struct Snapshot {
-    pub(super) lastUpdateId: u64,
-    pub(super) bids: Vec<Update>,
-    pub(super) asks: Vec<Update>,
+pub(crate) struct Snapshot {
+    pub(crate) lastUpdateId: u64,
+    #[serde(deserialize_with = "decode::levels")]
+    pub(crate) bids: Vec<Order>,
+    #[serde(deserialize_with = "decode::levels")]
+    pub(crate) asks: Vec<Order>,
 }
 
 /// <https://developers.binance.com/docs/binance-spot-api-docs/rest-api/market-data-endpoints#order-book>
 pub(super) async fn get_snapshot(
+    config: &SystemConfig,
     pair: &Pair,
     size: usize,
-    r_tb: &Arc<TokenBucket>,
-    w_tb: &Arc<TokenBucket>,
+    scheduler: &SnapshotScheduler,
 ) -> reqwest::Result<Snapshot> {
     let weight =
         if size <= 100 { 5 }
@@ -25,17 +27,22 @@ pub(super) async fn get_snapshot(
         else if size <= 1_000 { 50 }
         else { 250 };
 
-    r_tb.acquire(1).await;
-    w_tb.acquire(weight).await;
+    scheduler.acquire(pair, weight).await;
+    metrics::counter("market_view_snapshot_requests_total", pair_labels(pair)).inc();
+    metrics::counter("market_view_snapshot_weight_total", place_labels()).add(weight.into());
 
-    reqwest::Client::new()
+    let response = reqwest::Client::new()
         .get("https://data-api.binance.vision/api/v3/depth")
         .query(&json!({
             "symbol": pair.fused_upper(),
             "limit": size,
         }))
         .send()
-        .await?
+        .await?;
+    scheduler.rate_limiter().update(&config.log_prefix, response.status(), response.headers()).await;
+
+    response
+        .error_for_status()?
         .json::<Snapshot>()
         .await
 }
@@ -0,0 +1,43 @@
+use super::rate_limiter::RateLimiter;
+use crate::SystemConfig;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct Ticker {
+    symbol: String,
+    quoteVolume: Decimal,
+}
+
+/// Volume in quote asset within last 24 hours by symbol, truncated.
+///
+/// <https://developers.binance.com/docs/binance-spot-api-docs/rest-api/market-data-endpoints#24hr-ticker-price-change-statistics>
+pub(super) async fn get_quote_volumes(
+    config: &SystemConfig,
+    rl: &RateLimiter,
+) -> reqwest::Result<HashMap<String, u64>> {
+    // Weight of all symbols request.
+    rl.acquire(80).await;
+
+    let response = reqwest::Client::new()
+        .get("https://data-api.binance.vision/api/v3/ticker/24hr")
+        .query(&json!({
+            "type": "MINI",
+        }))
+        .send()
+        .await?;
+    rl.update(&config.log_prefix, response.status(), response.headers()).await;
+
+    Ok(HashMap::from_iter(
+        response
+            .error_for_status()?
+            .json::<Vec<Ticker>>()
+            .await?
+            .into_iter()
+            .map(|t| (t.symbol, t.quoteVolume.trunc().to_u64().unwrap_or(u64::MAX)))
+    ))
+}
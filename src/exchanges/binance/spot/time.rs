@@ -0,0 +1,65 @@
+use super::rate_limiter::RateLimiter;
+use crate::{ClockSync, SystemConfig};
+use backon::Retryable;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct ServerTime {
+    serverTime: u64,
+}
+
+/// Returns local time the request is sent at, its round trip and server time read in between.
+///
+/// <https://developers.binance.com/docs/binance-spot-api-docs/rest-api/general-endpoints#check-server-time>
+async fn get_server_time(
+    config: &SystemConfig,
+    client: &reqwest::Client,
+    rl: &RateLimiter,
+) -> reqwest::Result<(SystemTime, Duration, SystemTime)> {
+    rl.acquire(1).await;
+
+    let (sent, start) = (SystemTime::now(), Instant::now());
+    let response = client
+        .get("https://data-api.binance.vision/api/v3/time")
+        .send()
+        .await?;
+    let rtt = start.elapsed();
+    rl.update(&config.log_prefix, response.status(), response.headers()).await;
+
+    let server_time = response
+        .error_for_status()?
+        .json::<ServerTime>()
+        .await?
+        .serverTime;
+
+    Ok((sent, rtt, UNIX_EPOCH + Duration::from_millis(server_time)))
+}
+
+/// Samples server time every `clock_sync_interval`.
+///
+/// Every sample is preceded by a warm-up request, so the sampled one reuses its connection
+/// and its round trip doesn't include connection setup.
+pub(super) async fn run_clock_sync(config: SystemConfig, rl: Arc<RateLimiter>, clock: Arc<ClockSync>) {
+    let client = reqwest::Client::new();
+    let mut interval = tokio::time::interval(config.clock_sync_interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let sample = || async {
+            get_server_time(&config, &client, &rl).await?;
+            get_server_time(&config, &client, &rl).await
+        };
+        match sample.retry(backon::ExponentialBuilder::default()).await {
+            Ok((sent, rtt, server)) => clock.add_sample(sent, rtt, server),
+            Err(err) => {
+                log_event!(error, config.log_prefix, { ?err }, "server time request failed: {err:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use super::pair_labels;
+use crate::metrics::{self, Counter};
+use crate::trades::{Trade, Trades};
+use crate::{Pair, SystemConfig};
+
+/// Passes trades of a pair to its [`Trades`] and reports the missed ones.
+pub(super) struct PairTrades {
+    pair: Pair,
+    trades: Trades,
+    missed: Counter,
+}
+
+impl PairTrades {
+    pub(super) fn new(pair: &Pair, trades: Trades) -> Self {
+        Self {
+            pair: pair.clone(),
+            trades,
+            missed: metrics::counter("market_view_trades_missed_total", pair_labels(pair)),
+        }
+    }
+
+    pub(super) fn on_trade(&self, config: &SystemConfig, trade: Trade) {
+        let missed = self.trades.push(trade);
+        if missed == 0 {
+            return;
+        }
+        let pair = &self.pair;
+        log_event!(
+            warn, format_args!("{} [{pair}]:", config.log_prefix), { %pair, id = trade.id, missed },
+            "missed {missed} trades before {}", trade.id,
+        );
+
+        self.missed.add(missed);
+    }
+}
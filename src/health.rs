@@ -0,0 +1,232 @@
+//! Whether books of every Place are served from live connections,
+//! exported by [`serve`] for orchestrator probes.
+
+use crate::http_server::{self, Response};
+use crate::{Pair, Place, PlaceHandle};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairStatus {
+    /// Book is in sync with the exchange, otherwise it's waiting for a snapshot
+    /// or its connection is down.
+    pub live: bool,
+    /// `None` until the first event.
+    pub last_event_age: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaceStatus {
+    pub connections: usize,
+    pub connected: usize,
+    pub live_books: usize,
+    pub resyncing_books: usize,
+    pub pairs: HashMap<Pair, PairStatus>,
+    /// Smallest unused fraction of REST rate limits.
+    pub rate_limit_headroom: f64,
+    /// All connections are up and enough books are live and recently updated,
+    /// see [`Config::ready_min_live_ratio`](crate::Config::ready_min_live_ratio).
+    pub ready: bool,
+}
+
+#[derive(Debug, Default)]
+struct PairCell {
+    live: AtomicBool,
+    // Unix millis, zero until the first event.
+    last_event: AtomicU64,
+}
+
+/// Status of a pair, updated by its sync.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PairHealth(Arc<PairCell>);
+
+/// Status of a connection, updated by its task.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectionHealth(Arc<AtomicBool>);
+
+type Headroom = Box<dyn Fn() -> f64 + Send + Sync>;
+
+/// Status of the pairs and connections of a Place.
+pub(crate) struct PlaceHealth {
+    min_live_ratio: f64,
+    max_event_age: Duration,
+    // Set once rate limits are known.
+    headroom: OnceLock<Headroom>,
+    connections: Mutex<BTreeMap<usize, ConnectionHealth>>,
+    pairs: HashMap<Pair, PairHealth>,
+}
+
+impl PairHealth {
+    #[cfg(test)]
+    pub(crate) fn live(&self) -> bool {
+        self.0.live.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_live(&self, live: bool) {
+        self.0.live.store(live, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_event(&self, received: SystemTime) {
+        let millis = received.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        self.0.last_event.store(millis as u64, Ordering::Relaxed);
+    }
+}
+
+impl ConnectionHealth {
+    pub(crate) fn set_connected(&self, connected: bool) {
+        self.0.store(connected, Ordering::Relaxed);
+    }
+}
+
+impl PlaceHealth {
+    pub(crate) fn new<'a>(
+        min_live_ratio: f64,
+        max_event_age: Duration,
+        pairs: impl IntoIterator<Item = &'a Pair>,
+    ) -> Self {
+        Self {
+            min_live_ratio,
+            max_event_age,
+            headroom: OnceLock::new(),
+            connections: Mutex::default(),
+            pairs: HashMap::from_iter(pairs.into_iter().map(|pair| (pair.clone(), PairHealth::default()))),
+        }
+    }
+
+    /// Sets the source of rate limit headroom.
+    pub(crate) fn set_headroom(&self, headroom: Headroom) {
+        let _ = self.headroom.set(headroom);
+    }
+
+    pub(crate) fn pair(&self, pair: &Pair) -> PairHealth {
+        self.pairs[pair].clone()
+    }
+
+    pub(crate) fn connection(&self, id: usize) -> ConnectionHealth {
+        self.connections.lock().unwrap().entry(id).or_default().clone()
+    }
+
+    pub(crate) fn status(&self, now: SystemTime) -> PlaceStatus {
+        let pairs = HashMap::<_, _>::from_iter(self.pairs.iter().map(|(pair, PairHealth(cell))| {
+            let last_event = cell.last_event.load(Ordering::Relaxed);
+            let last_event_age = (last_event > 0).then(|| {
+                now.duration_since(UNIX_EPOCH + Duration::from_millis(last_event)).unwrap_or_default()
+            });
+
+            (pair.clone(), PairStatus { live: cell.live.load(Ordering::Relaxed), last_event_age })
+        }));
+
+        let (connections, connected) = {
+            let connections = self.connections.lock().unwrap();
+            (connections.len(), connections.values().filter(|c| c.0.load(Ordering::Relaxed)).count())
+        };
+        let live_books = pairs.values().filter(|p| p.live).count();
+        let fresh_books = pairs.values()
+            .filter(|p| p.live && p.last_event_age.is_some_and(|age| age <= self.max_event_age))
+            .count();
+        let ready = connections > 0
+            && connected == connections
+            && fresh_books as f64 >= self.min_live_ratio * pairs.len() as f64;
+
+        PlaceStatus {
+            connections,
+            connected,
+            live_books,
+            resyncing_books: pairs.len() - live_books,
+            pairs,
+            rate_limit_headroom: self.headroom.get().map_or(1.0, |headroom| headroom()),
+            ready,
+        }
+    }
+}
+
+fn to_json(status: &HashMap<Place, PlaceStatus>) -> String {
+    let places = Vec::from_iter(status.iter().map(|((exchange, platform), status)| json!({
+        "exchange": format!("{exchange:?}"),
+        "platform": format!("{platform:?}"),
+        "ready": status.ready,
+        "connections": status.connections,
+        "connected": status.connected,
+        "live_books": status.live_books,
+        "resyncing_books": status.resyncing_books,
+        "rate_limit_headroom": status.rate_limit_headroom,
+        "pairs": serde_json::Map::from_iter(status.pairs.iter().map(|(pair, status)| (
+            pair.to_string(),
+            json!({
+                "live": status.live,
+                "last_event_age_ms": status.last_event_age.map(|age| age.as_millis() as u64),
+            }),
+        ))),
+    })));
+
+    json!({ "places": places }).to_string()
+}
+
+/// Serves [`PlaceHandle::status`] of the `places` as JSON at `addr`:
+///
+/// - `GET /health` is always `200 OK` while the process is up;
+/// - `GET /ready` is `200 OK` if every Place is ready, `503 Service Unavailable` otherwise.
+pub async fn serve(addr: SocketAddr, places: HashMap<Place, PlaceHandle>) -> io::Result<()> {
+    http_server::serve(addr, move |path| {
+        let status = HashMap::from_iter(places.iter().map(|(place, handle)| (place.clone(), handle.status())));
+        let ready = !status.is_empty() && status.values().all(|s| s.ready);
+
+        let code = match path {
+            "/health" => "200 OK",
+            "/ready" if ready => "200 OK",
+            "/ready" => "503 Service Unavailable",
+            _ => return None,
+        };
+        Some(Response { status: code, content_type: "application/json", body: to_json(&status) })
+    }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Exchange, Platform};
+
+    #[test]
+    fn readiness() {
+        let health = |live: &[bool]| {
+            let pairs = Vec::from_iter((0..live.len()).map(|idx| Pair::new(idx.to_string(), String::from("usdt"))));
+            let health = PlaceHealth::new(0.5, Duration::from_secs(60), &pairs);
+            health.set_headroom(Box::new(|| 0.25));
+            health.connection(1).set_connected(true);
+
+            for (pair, &live) in pairs.iter().zip(live) {
+                let pair = health.pair(pair);
+                pair.set_live(live);
+                pair.on_event(UNIX_EPOCH + Duration::from_secs(1000));
+            }
+            health
+        };
+        let now = UNIX_EPOCH + Duration::from_secs(1030);
+
+        let status = health(&[true, false, true, false]).status(now);
+        assert!(status.ready);
+        assert_eq!((status.live_books, status.resyncing_books), (2, 2));
+        assert_eq!(status.rate_limit_headroom, 0.25);
+        assert_eq!(
+            status.pairs[&Pair::new(String::from("0"), String::from("usdt"))],
+            PairStatus { live: true, last_event_age: Some(Duration::from_secs(30)) },
+        );
+
+        assert!(!health(&[true, false, false]).status(now).ready);
+        // Events are too old.
+        assert!(!health(&[true, true]).status(now + Duration::from_secs(60)).ready);
+
+        let h = health(&[true]);
+        h.connection(1).set_connected(false);
+        assert!(!h.status(now).ready);
+
+        let json = to_json(&HashMap::from([((Exchange::Binance, Platform::Spot), health(&[true]).status(now))]));
+        assert!(json.contains(r#""ready":true"#));
+        assert!(json.contains(r#""0/USDT":{"last_event_age_ms":30000,"live":true}"#));
+    }
+}
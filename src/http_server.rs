@@ -0,0 +1,49 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+pub(crate) struct Response {
+    pub(crate) status: &'static str,
+    pub(crate) content_type: &'static str,
+    pub(crate) body: String,
+}
+
+/// Minimal HTTP/1.1 server answering `GET` requests by `handle(path)`, 404 if it returns `None`.
+/// It's only meant for scrapes and probes, so every connection serves a single request.
+pub(crate) async fn serve(
+    addr: SocketAddr,
+    handle: impl Fn(&str) -> Option<Response> + Send + Sync + 'static,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    let handle = Arc::new(handle);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(serve_client(stream, Arc::clone(&handle)));
+    }
+}
+
+async fn serve_client(mut stream: TcpStream, handle: Arc<impl Fn(&str) -> Option<Response>>) {
+    // Request line and headers of a probe fit into a single small read.
+    let mut request = [0; 1024];
+    let Ok(n) = stream.read(&mut request).await else {
+        return;
+    };
+    let request = String::from_utf8_lossy(&request[..n]);
+
+    let response = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", path] => handle(path),
+        _ => None,
+    };
+    let response = match response {
+        Some(Response { status, content_type, body }) => format!(
+            "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\n\
+            Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len(),
+        ),
+        None => String::from("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"),
+    };
+    let _ = stream.write_all(response.as_bytes()).await;
+}
@@ -0,0 +1,133 @@
+use crate::Pair;
+use rust_decimal::Decimal;
+
+/// Trading rules and metadata of a single market.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instrument {
+    pub pair: Pair,
+    /// Exchange symbol status, e.g. `TRADING` or `BREAK`.
+    pub status: String,
+    pub base_precision: u32,
+    pub quote_precision: u32,
+    pub min_price: Decimal,
+    pub max_price: Decimal,
+    /// Price step, zero if prices are not restricted to a grid.
+    pub tick_size: Decimal,
+    pub min_qty: Decimal,
+    pub max_qty: Decimal,
+    /// Quantity step, zero if quantities are not restricted to a grid.
+    pub step_size: Decimal,
+    /// Minimal `price * qty` value of an order.
+    pub min_notional: Option<Decimal>,
+    /// Maximal `price * qty` value of an order.
+    pub max_notional: Option<Decimal>,
+    pub permissions: Vec<String>,
+    pub order_types: Vec<String>,
+    pub oco_allowed: bool,
+}
+
+fn floor_to_step(value: Decimal, step: Decimal) -> Decimal {
+    if step.is_zero() {
+        value
+    } else {
+        (value / step).floor() * step
+    }
+}
+
+fn ceil_to_step(value: Decimal, step: Decimal) -> Decimal {
+    if step.is_zero() {
+        value
+    } else {
+        (value / step).ceil() * step
+    }
+}
+
+fn is_on_step(value: Decimal, step: Decimal) -> bool {
+    step.is_zero() || (value % step).is_zero()
+}
+
+impl Instrument {
+    pub fn is_trading(&self) -> bool {
+        self.status == "TRADING"
+    }
+
+    /// Rounds price down to the closest valid tick.
+    pub fn floor_price(&self, price: Decimal) -> Decimal {
+        floor_to_step(price, self.tick_size).normalize()
+    }
+
+    /// Rounds price up to the closest valid tick.
+    pub fn ceil_price(&self, price: Decimal) -> Decimal {
+        ceil_to_step(price, self.tick_size).normalize()
+    }
+
+    /// Rounds quantity down to the closest valid step,
+    /// so order never exceeds the desired size.
+    pub fn floor_qty(&self, qty: Decimal) -> Decimal {
+        floor_to_step(qty, self.step_size).normalize()
+    }
+
+    pub fn is_valid_price(&self, price: Decimal) -> bool {
+        price >= self.min_price
+            && (self.max_price.is_zero() || price <= self.max_price)
+            && is_on_step(price, self.tick_size)
+    }
+
+    pub fn is_valid_qty(&self, qty: Decimal) -> bool {
+        qty >= self.min_qty
+            && (self.max_qty.is_zero() || qty <= self.max_qty)
+            && is_on_step(qty, self.step_size)
+    }
+
+    pub fn is_valid_notional(&self, price: Decimal, qty: Decimal) -> bool {
+        let notional = price * qty;
+
+        self.min_notional.is_none_or(|min| notional >= min)
+            && self.max_notional.is_none_or(|max| notional <= max)
+    }
+
+    /// Checks that order passes price, quantity and notional filters.
+    pub fn is_valid_order(&self, price: Decimal, qty: Decimal) -> bool {
+        self.is_valid_price(price)
+            && self.is_valid_qty(qty)
+            && self.is_valid_notional(price, qty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn instrument() {
+        let instrument = Instrument {
+            pair: Pair::new(String::from("btc"), String::from("usdt")),
+            status: String::from("TRADING"),
+            base_precision: 8,
+            quote_precision: 8,
+            min_price: dec!(0.01),
+            max_price: dec!(1000000),
+            tick_size: dec!(0.01),
+            min_qty: dec!(0.00001),
+            max_qty: dec!(9000),
+            step_size: dec!(0.00001),
+            min_notional: Some(dec!(5)),
+            max_notional: None,
+            permissions: vec![String::from("SPOT")],
+            order_types: vec![String::from("LIMIT")],
+            oco_allowed: true,
+        };
+
+        assert!(instrument.is_trading());
+        assert_eq!(instrument.floor_price(dec!(104321.987)), dec!(104321.98));
+        assert_eq!(instrument.ceil_price(dec!(104321.981)), dec!(104321.99));
+        assert_eq!(instrument.ceil_price(dec!(104321.98)), dec!(104321.98));
+        assert_eq!(instrument.floor_qty(dec!(0.123456789)), dec!(0.12345));
+
+        assert!(instrument.is_valid_order(dec!(100000.01), dec!(0.0001)));
+        assert!(!instrument.is_valid_price(dec!(100000.015)));
+        assert!(!instrument.is_valid_qty(dec!(0.000001)));
+        assert!(!instrument.is_valid_notional(dec!(100000), dec!(0.00001)));
+    }
+}
@@ -1,56 +1,207 @@
+use crate::{ClockSync, Pair};
+use hdrhistogram::Histogram;
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::sync::mpsc;
 
+/// Percentiles of recorded latencies, with microsecond resolution and 1% precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Percentiles {
+    pub count: u64,
+    pub p50: Duration,
+    pub p99: Duration,
+    pub p999: Duration,
+    pub max: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Latency {
+    /// From event time set by exchange to the moment its message is received,
+    /// corrected by the estimated [`clock_offset`](crate::PlaceHandle::clock_offset).
+    pub exchange_to_receive: Percentiles,
+    /// From the moment message is received to the moment event is applied to the book.
+    /// Events replayed after snapshot are not included, as they wait for it.
+    pub receive_to_apply: Percentiles,
+}
+
+impl Percentiles {
+    fn new(histogram: &Histogram<u64>) -> Self {
+        let at = |quantile| Duration::from_micros(histogram.value_at_quantile(quantile));
+
+        Self {
+            count: histogram.len(),
+            p50: at(0.5),
+            p99: at(0.99),
+            p999: at(0.999),
+            max: Duration::from_micros(histogram.max()),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Histograms {
+    exchange_to_receive: Histogram<u64>,
+    receive_to_apply: Histogram<u64>,
+}
+
+impl Histograms {
+    fn new() -> Self {
+        // Auto resizing, so memory depends on the largest recorded value.
+        let new = || Histogram::new(2).unwrap();
+
+        Self { exchange_to_receive: new(), receive_to_apply: new() }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.exchange_to_receive.add(&other.exchange_to_receive).unwrap();
+        self.receive_to_apply.add(&other.receive_to_apply).unwrap();
+    }
+
+    fn reset(&mut self) {
+        self.exchange_to_receive.reset();
+        self.receive_to_apply.reset();
+    }
+
+    fn latency(&self) -> Latency {
+        Latency {
+            exchange_to_receive: Percentiles::new(&self.exchange_to_receive),
+            receive_to_apply: Percentiles::new(&self.receive_to_apply),
+        }
+    }
+}
+
+/// Records latencies of a pair, uncontended except for periodic collection.
+#[derive(Clone)]
+pub(crate) struct LatencyRecorder {
+    histograms: Arc<Mutex<Histograms>>,
+    clock: Arc<ClockSync>,
+}
+
+impl LatencyRecorder {
+    /// Recorder which isn't collected by any meter.
+    #[cfg(test)]
+    pub(crate) fn detached() -> Self {
+        Self { histograms: Arc::new(Mutex::new(Histograms::new())), clock: Arc::new(ClockSync::new()) }
+    }
+
+    /// Clock of the exchange, which event times should be compared with.
+    pub(crate) fn clock(&self) -> &ClockSync {
+        &self.clock
+    }
+
+    pub(crate) fn record(&self, exchange_to_receive: Duration, receive_to_apply: Option<Duration>) {
+        let mut histograms = self.histograms.lock().unwrap();
+
+        histograms.exchange_to_receive.record(exchange_to_receive.as_micros() as u64).unwrap();
+        if let Some(receive_to_apply) = receive_to_apply {
+            histograms.receive_to_apply.record(receive_to_apply.as_micros() as u64).unwrap();
+        }
+    }
+}
+
+struct PairHistograms {
+    recent: LatencyRecorder,
+    total: Histograms,
+}
+
+type Pairs = Arc<Mutex<HashMap<Pair, PairHistograms>>>;
+
+/// Latencies recorded by pairs of a Place, collected by [`LatencyMeter::run_check`].
+#[derive(Clone)]
 pub(crate) struct LatencyMeter {
-    recv_jh: tokio::task::JoinHandle<()>,
-    check_jh: tokio::task::JoinHandle<()>,
+    pairs: Pairs,
+    clock: Arc<ClockSync>,
 }
 
 impl LatencyMeter {
-    pub(crate) fn new(prefix: String, interval: Duration, mut rx: mpsc::UnboundedReceiver<Duration>) -> Self {
-        let vec = Arc::new(Mutex::new(Vec::new()));
+    pub(crate) fn new(clock: Arc<ClockSync>) -> Self {
+        Self { pairs: Pairs::default(), clock }
+    }
+
+    /// Collects recorded latencies every `interval`, warning if any of them exceeds `max_latency`.
+    pub(crate) async fn run_check(self, prefix: String, interval: Duration, max_latency: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut recent = Histograms::new();
+
+        loop {
+            interval.tick().await;
 
-        let recv_jh = tokio::spawn({
-            let vec = Arc::clone(&vec);
+            for pair in self.pairs.lock().unwrap().values_mut() {
+                let mut histograms = pair.recent.histograms.lock().unwrap();
 
-            async move {
-                while let Some(latency) = rx.recv().await {
-                    vec.lock().unwrap().push(latency);
-                }
+                pair.total.add(&histograms);
+                recent.add(&histograms);
+                histograms.reset();
             }
-        });
 
-        let check_jh = tokio::spawn({
-            let vec = Arc::clone(&vec);
-            let mut interval = tokio::time::interval(interval);
-            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let latency = recent.latency().exchange_to_receive;
+            if latency.max > max_latency {
+                log_event!(
+                    warn, prefix,
+                    { p50 = ?latency.p50, p99 = ?latency.p99, max = ?latency.max, events = latency.count },
+                    "high latency - p50 {:?}, p99 {:?}, max {:?} ({} events)",
+                    latency.p50, latency.p99, latency.max, latency.count,
+                );
+            }
+            recent.reset();
+        }
+    }
 
-            async move {
-                loop {
-                    interval.tick().await;
+    pub(crate) fn recorder(&self, pair: &Pair) -> LatencyRecorder {
+        let mut pairs = self.pairs.lock().unwrap();
+        let pair = pairs.entry(pair.clone()).or_insert_with(|| PairHistograms {
+            recent: LatencyRecorder {
+                histograms: Arc::new(Mutex::new(Histograms::new())),
+                clock: Arc::clone(&self.clock),
+            },
+            total: Histograms::new(),
+        });
 
-                    {
-                        let mut vec = vec.lock().unwrap();
+        pair.recent.clone()
+    }
 
-                        if !vec.is_empty() {
-                            let mean = vec.iter().sum::<Duration>() / vec.len() as u32;
-                            log::warning!("{prefix} high latency - {mean:?} ({} events)", vec.len());
+    /// Latencies of all pairs since start, as of the last check.
+    pub(crate) fn latency(&self) -> Latency {
+        let mut total = Histograms::new();
 
-                            vec.clear();
-                        }
-                    }
-                }
-            }
-        });
+        for pair in self.pairs.lock().unwrap().values() {
+            total.add(&pair.total);
+        }
+        total.latency()
+    }
 
-        Self { recv_jh, check_jh }
+    /// Latencies of the pair since start, as of the last check.
+    pub(crate) fn pair_latency(&self, pair: &Pair) -> Option<Latency> {
+        self.pairs.lock().unwrap().get(pair).map(|pair| pair.total.latency())
     }
 }
 
-impl Drop for LatencyMeter {
-    fn drop(&mut self) {
-        self.recv_jh.abort();
-        self.check_jh.abort();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn percentiles() {
+        let pair = Pair::new(String::from("btc"), String::from("usdt"));
+        let meter = LatencyMeter::new(Arc::new(ClockSync::new()));
+        tokio::spawn(meter.clone().run_check(String::new(), Duration::from_secs(1), Duration::from_secs(5)));
+        let recorder = meter.recorder(&pair);
+
+        for ms in 1..=1000 {
+            let apply = (ms % 10 == 0).then(|| Duration::from_micros(ms));
+            recorder.record(Duration::from_millis(ms), apply);
+        }
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+
+        let stats = meter.pair_latency(&pair).unwrap();
+        let network = stats.exchange_to_receive;
+        assert_eq!(network.count, 1000);
+        assert!(network.p50.abs_diff(Duration::from_millis(500)) <= Duration::from_millis(5));
+        assert!(network.p99.abs_diff(Duration::from_millis(990)) <= Duration::from_millis(10));
+        assert!(network.max.abs_diff(Duration::from_millis(1000)) <= Duration::from_millis(10));
+        assert_eq!(stats.receive_to_apply.count, 100);
+
+        assert_eq!(meter.latency(), stats);
     }
 }
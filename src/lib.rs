@@ -1,19 +1,91 @@
+/// Spawns `task` instrumented by span of the given name and fields with `tracing` feature,
+/// otherwise just spawns it.
+macro_rules! spawn_in_span {
+    ($task:expr, $($span:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        let task = tracing::Instrument::instrument($task, tracing::info_span!($($span)+));
+        #[cfg(not(feature = "tracing"))]
+        let task = $task;
+        tokio::spawn(task)
+    }};
+}
+
+/// Spawns `task` within the current span with `tracing` feature, otherwise just spawns it.
+macro_rules! spawn_in_current_span {
+    ($task:expr) => {{
+        #[cfg(feature = "tracing")]
+        let task = tracing::Instrument::in_current_span($task);
+        #[cfg(not(feature = "tracing"))]
+        let task = $task;
+        tokio::spawn(task)
+    }};
+}
+
+/// Logs a structured event with `tracing` feature, otherwise a line starting with `prefix`,
+/// which carries the context of spans and fields.
+///
+/// `log_event!(warn, prefix, { %pair, ?timeout }, "no events for {timeout:?}")`
+macro_rules! log_event {
+    (@tracing $level:ident, { }, $($message:tt)+) => {
+        tracing::$level!($($message)+)
+    };
+    (@tracing $level:ident, { $($field:tt)+ }, $($message:tt)+) => {
+        tracing::$level!($($field)+, $($message)+)
+    };
+    (@log warn, $($arg:tt)+) => {
+        log::warning!($($arg)+)
+    };
+    (@log $level:ident, $($arg:tt)+) => {
+        log::$level!($($arg)+)
+    };
+    ($level:ident, $prefix:expr, { $($field:tt)* }, $($message:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        {
+            let _ = &$prefix;
+            log_event!(@tracing $level, { $($field)* }, $($message)+);
+        }
+        #[cfg(not(feature = "tracing"))]
+        log_event!(@log $level, "{} {}", $prefix, format_args!($($message)+));
+    }};
+}
+
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
 mod book;
+mod clock_sync;
 mod config;
 pub mod exchanges;
 mod hashmap_chunks;
+pub mod health;
+mod http_server;
+mod instrument;
 mod latency_meter;
+pub mod metrics;
 mod pair;
+mod place;
+pub mod rate_limit;
+mod reconnect_governor;
+mod shared_book;
 mod token_bucket;
+pub mod trades;
 
-pub use book::{Book, Order};
-pub use config::Config;
+pub use book::{Bbo, Book, Order, Side, SideKind};
+pub use clock_sync::ClockOffset;
+use clock_sync::ClockSync;
+pub use config::{Config, ConnectionMode, InvariantPolicy, PartialDepth, TradeStream};
 use config::SystemConfig;
 use hashmap_chunks::HashMapChunks;
-use latency_meter::LatencyMeter;
+pub use instrument::Instrument;
+pub use latency_meter::{Latency, Percentiles};
+use latency_meter::{LatencyMeter, LatencyRecorder};
 pub use pair::Pair;
+pub use place::PlaceHandle;
+use reconnect_governor::{Backoff, ReconnectGovernor};
+use shared_book::BookWriter;
+pub use shared_book::SharedBook;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use token_bucket::TokenBucket;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -28,39 +100,33 @@ pub enum Platform {
 
 pub type Place = (Exchange, Platform);
 
-pub fn start(configs: Vec<Config>) -> HashMap<Place, HashMap<Pair, Arc<Mutex<Book>>>> {
+pub fn start(configs: Vec<Config>) -> HashMap<Place, PlaceHandle> {
     HashMap::from_iter(
-        configs.into_iter().map(|config| (
-            config.place,
-            {
-                let books = HashMap::from_iter(
-                    config.pairs.into_iter().map(|pair| (
-                        pair,
-                        Arc::new(Mutex::new(Book::new(config.book_cap)))
-                    ))
-                );
+        configs.into_iter().map(|config| {
+            let handle = PlaceHandle::new(&config);
 
-                let spawner = match config.place {
-                    (Exchange::Binance, Platform::Spot) => exchanges::binance::spot::spawn,
-                };
-                tokio::spawn(spawner(config.system, books.clone()));
+            let spawner = match config.place {
+                (Exchange::Binance, Platform::Spot) => exchanges::binance::spot::spawn,
+            };
+            spawn_in_span!(
+                spawner(config.system, handle.clone()),
+                "place", exchange = ?config.place.0, platform = ?config.place.1,
+            );
 
-                books
-            }
-        ))
+            (config.place, handle)
+        })
     )
 }
 
-pub fn copy_books(
-    books: &HashMap<Place, HashMap<Pair, Arc<Mutex<Book>>>>
-) -> HashMap<&Place, HashMap<&Pair, Book>> {
+/// Takes consistent snapshots of all books without blocking their updates.
+pub fn copy_books(places: &HashMap<Place, PlaceHandle>) -> HashMap<&Place, HashMap<&Pair, Arc<Book>>> {
     HashMap::from_iter(
-        books.iter().map(|(place, books)| (
+        places.iter().map(|(place, handle)| (
             place,
             HashMap::from_iter(
-                books.iter().map(|(pair, book)| (
+                handle.books().iter().map(|(pair, book)| (
                     pair,
-                    book.lock().unwrap().clone()
+                    book.load()
                 ))
             )
         ))
@@ -0,0 +1,236 @@
+//! Metrics of notable events, labeled by Place and pair.
+//!
+//! Metrics are collected with `metrics` feature, which is enabled by default,
+//! and exported in Prometheus text format by [`render`] or [`serve`].
+//! Without it metrics are never registered and nothing is exported.
+
+use crate::http_server::{self, Response};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub type Labels = Vec<(&'static str, String)>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CounterValue {
+    pub name: &'static str,
+    pub labels: Labels,
+    pub value: u64,
+}
+
+/// Upper bounds of latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 12] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Handle to a registered counter, cheap to increment.
+#[derive(Debug, Clone)]
+pub(crate) struct Counter(Arc<AtomicU64>);
+
+/// Handle to a registered gauge.
+#[derive(Debug, Clone)]
+pub(crate) struct Gauge(Arc<AtomicU64>);
+
+/// Handle to a registered latency histogram.
+#[derive(Debug, Clone)]
+pub(crate) struct Histogram(Arc<HistogramValues>);
+
+#[derive(Debug, Default)]
+struct HistogramValues {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+#[derive(Debug, Clone)]
+enum Metric {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(Histogram),
+}
+
+// Sorted by name, so metrics of the same family are rendered together.
+static METRICS: Mutex<BTreeMap<(&'static str, Labels), Metric>> = Mutex::new(BTreeMap::new());
+
+impl Counter {
+    pub(crate) fn inc(&self) {
+        self.add(1);
+    }
+
+    pub(crate) fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+impl Gauge {
+    pub(crate) fn set(&self, value: u64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+}
+
+impl Histogram {
+    pub(crate) fn observe(&self, value: Duration) {
+        let secs = value.as_secs_f64();
+
+        if let Some(idx) = LATENCY_BUCKETS.iter().position(|&bound| secs <= bound) {
+            self.0.buckets[idx].fetch_add(1, Ordering::Relaxed);
+        }
+        self.0.count.fetch_add(1, Ordering::Relaxed);
+        self.0.sum_nanos.fetch_add(value.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Returns already registered metric with the same name and labels or registers a new one.
+#[cfg(feature = "metrics")]
+fn register(name: &'static str, labels: Labels, new: impl FnOnce() -> Metric) -> Metric {
+    METRICS.lock().unwrap().entry((name, labels)).or_insert_with(new).clone()
+}
+
+/// Returns a new metric, which is updated but never collected.
+#[cfg(not(feature = "metrics"))]
+fn register(_name: &'static str, _labels: Labels, new: impl FnOnce() -> Metric) -> Metric {
+    new()
+}
+
+pub(crate) fn counter(name: &'static str, labels: Labels) -> Counter {
+    match register(name, labels, || Metric::Counter(Counter(Arc::default()))) {
+        Metric::Counter(counter) => counter,
+        _ => panic!("{name} is not a counter"),
+    }
+}
+
+pub(crate) fn gauge(name: &'static str, labels: Labels) -> Gauge {
+    match register(name, labels, || Metric::Gauge(Gauge(Arc::default()))) {
+        Metric::Gauge(gauge) => gauge,
+        _ => panic!("{name} is not a gauge"),
+    }
+}
+
+pub(crate) fn histogram(name: &'static str, labels: Labels) -> Histogram {
+    match register(name, labels, || Metric::Histogram(Histogram(Arc::default()))) {
+        Metric::Histogram(histogram) => histogram,
+        _ => panic!("{name} is not a histogram"),
+    }
+}
+
+/// Current values of all counters.
+pub fn counters() -> Vec<CounterValue> {
+    METRICS.lock().unwrap()
+        .iter()
+        .filter_map(|((name, labels), metric)| match metric {
+            Metric::Counter(counter) => Some(CounterValue {
+                name,
+                labels: labels.clone(),
+                value: counter.0.load(Ordering::Relaxed),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+fn write_labels(out: &mut String, labels: &[(&str, String)], extra: Option<(&str, &str)>) {
+    if labels.is_empty() && extra.is_none() {
+        return;
+    }
+    let labels = labels.iter().map(|(k, v)| (*k, v.as_str())).chain(extra);
+
+    for (idx, (key, value)) in labels.enumerate() {
+        out.push(if idx == 0 { '{' } else { ',' });
+        let value = value.replace('\\', r"\\").replace('"', "\\\"").replace('\n', r"\n");
+        write!(out, "{key}=\"{value}\"").unwrap();
+    }
+    out.push('}');
+}
+
+/// All metrics in Prometheus text exposition format.
+///
+/// <https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format>
+pub fn render() -> String {
+    let metrics = METRICS.lock().unwrap();
+    let mut out = String::new();
+    let mut family = "";
+
+    for ((name, labels), metric) in metrics.iter() {
+        if *name != family {
+            let kind = match metric {
+                Metric::Counter(_) => "counter",
+                Metric::Gauge(_) => "gauge",
+                Metric::Histogram(_) => "histogram",
+            };
+            writeln!(out, "# TYPE {name} {kind}").unwrap();
+            family = name;
+        }
+
+        match metric {
+            Metric::Counter(Counter(value)) | Metric::Gauge(Gauge(value)) => {
+                out.push_str(name);
+                write_labels(&mut out, labels, None);
+                writeln!(out, " {}", value.load(Ordering::Relaxed)).unwrap();
+            }
+            Metric::Histogram(Histogram(values)) => {
+                let mut cumulative = 0;
+
+                for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&values.buckets) {
+                    cumulative += bucket.load(Ordering::Relaxed);
+                    write!(out, "{name}_bucket").unwrap();
+                    write_labels(&mut out, labels, Some(("le", &bound.to_string())));
+                    writeln!(out, " {cumulative}").unwrap();
+                }
+                let count = values.count.load(Ordering::Relaxed);
+                write!(out, "{name}_bucket").unwrap();
+                write_labels(&mut out, labels, Some(("le", "+Inf")));
+                writeln!(out, " {count}").unwrap();
+
+                write!(out, "{name}_sum").unwrap();
+                write_labels(&mut out, labels, None);
+                let sum = Duration::from_nanos(values.sum_nanos.load(Ordering::Relaxed));
+                writeln!(out, " {}", sum.as_secs_f64()).unwrap();
+
+                write!(out, "{name}_count").unwrap();
+                write_labels(&mut out, labels, None);
+                writeln!(out, " {count}").unwrap();
+            }
+        }
+    }
+
+    out
+}
+
+/// Serves [`render`]ed metrics on `GET /metrics` at `addr`.
+pub async fn serve(addr: SocketAddr) -> io::Result<()> {
+    http_server::serve(addr, |path| (path == "/metrics").then(|| Response {
+        status: "200 OK",
+        content_type: "text/plain; version=0.0.4",
+        body: render(),
+    })).await
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_metrics() {
+        let labels = vec![("pair", String::from("RENDER/USDT"))];
+
+        counter("test_render_total", labels.clone()).add(3);
+        gauge("test_render_depth", labels.clone()).set(42);
+        let histogram = histogram("test_render_latency_seconds", labels.clone());
+        histogram.observe(Duration::from_millis(20));
+        histogram.observe(Duration::from_secs(60));
+
+        let out = render();
+
+        assert!(out.contains("# TYPE test_render_total counter\ntest_render_total{pair=\"RENDER/USDT\"} 3\n"));
+        assert!(out.contains("# TYPE test_render_depth gauge\ntest_render_depth{pair=\"RENDER/USDT\"} 42\n"));
+        assert!(out.contains("# TYPE test_render_latency_seconds histogram\n"));
+        assert!(out.contains("test_render_latency_seconds_bucket{pair=\"RENDER/USDT\",le=\"0.01\"} 0\n"));
+        assert!(out.contains("test_render_latency_seconds_bucket{pair=\"RENDER/USDT\",le=\"0.025\"} 1\n"));
+        assert!(out.contains("test_render_latency_seconds_bucket{pair=\"RENDER/USDT\",le=\"30\"} 1\n"));
+        assert!(out.contains("test_render_latency_seconds_bucket{pair=\"RENDER/USDT\",le=\"+Inf\"} 2\n"));
+        assert!(out.contains("test_render_latency_seconds_sum{pair=\"RENDER/USDT\"} 60.02\n"));
+        assert!(out.contains("test_render_latency_seconds_count{pair=\"RENDER/USDT\"} 2\n"));
+    }
+}
@@ -0,0 +1,67 @@
+use crate::health::{PlaceHealth, PlaceStatus};
+use crate::trades::Trades;
+use crate::{Book, ClockOffset, ClockSync, Config, Latency, LatencyMeter, Pair, SharedBook};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Books and state of a Place started by [`crate::start`], cheap to clone.
+#[derive(Clone)]
+pub struct PlaceHandle {
+    books: HashMap<Pair, SharedBook>,
+    pub(crate) trades: HashMap<Pair, Trades>,
+    pub(crate) clock: Arc<ClockSync>,
+    pub(crate) latency: LatencyMeter,
+    pub(crate) health: Arc<PlaceHealth>,
+}
+
+impl PlaceHandle {
+    pub(crate) fn new(config: &Config) -> Self {
+        let books = HashMap::from_iter(
+            config.pairs.iter().map(|pair| (
+                pair.clone(),
+                SharedBook::new(Book::new(config.book_cap, config.side_kind))
+            ))
+        );
+        let trades = HashMap::from_iter(
+            config.pairs.iter().map(|pair| (pair.clone(), Trades::new(config.system.trade_buffer)))
+        );
+        let clock = Arc::new(ClockSync::new());
+        let health = PlaceHealth::new(
+            config.system.ready_min_live_ratio, config.system.ready_max_event_age, &config.pairs,
+        );
+
+        Self { books, trades, latency: LatencyMeter::new(Arc::clone(&clock)), clock, health: Arc::new(health) }
+    }
+
+    pub fn books(&self) -> &HashMap<Pair, SharedBook> {
+        &self.books
+    }
+
+    /// Trades of the pair, which stay empty unless the Place streams them.
+    pub fn trades(&self, pair: &Pair) -> Option<&Trades> {
+        self.trades.get(pair)
+    }
+
+    /// Estimated clock offset of the exchange, `None` until it's measured.
+    pub fn clock_offset(&self) -> Option<ClockOffset> {
+        self.clock.offset()
+    }
+
+    /// Latencies of all pairs since start,
+    /// updated every [`Config::latency_check_interval`](crate::Config::latency_check_interval).
+    pub fn latency(&self) -> Latency {
+        self.latency.latency()
+    }
+
+    /// Latencies of the pair since start,
+    /// updated every [`Config::latency_check_interval`](crate::Config::latency_check_interval).
+    pub fn pair_latency(&self, pair: &Pair) -> Option<Latency> {
+        self.latency.pair_latency(pair)
+    }
+
+    /// Whether books are served from live connections, see [`crate::health::serve`].
+    pub fn status(&self) -> PlaceStatus {
+        self.health.status(SystemTime::now())
+    }
+}
@@ -0,0 +1,87 @@
+//! Storage of rate limit budgets.
+//!
+//! Exchanges apply REST limits per IP, so processes running on the same host
+//! should draw permits from a shared store instead of their own local ones.
+
+mod file;
+mod local;
+#[cfg(unix)]
+mod socket;
+
+pub use file::FileBackend;
+use futures::future::BoxFuture;
+pub use local::LocalBackend;
+#[cfg(unix)]
+pub use socket::{serve_coordinator, SocketBackend};
+use std::io;
+use std::time::Duration;
+
+/// Allows `cap` permits per `interval`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub cap: usize,
+    pub interval: Duration,
+}
+
+pub trait RateLimitBackend: Send + Sync {
+    /// Takes `n` permits of the limit `key` if they are available,
+    /// otherwise returns how long to wait until they are refilled.
+    fn try_acquire<'a>(
+        &'a self,
+        key: &'a str,
+        limit: Limit,
+        n: u32,
+    ) -> BoxFuture<'a, io::Result<Result<(), Duration>>>;
+
+    /// Reconciles the limit `key` with amount of permits `used` within the current interval
+    /// as reported by the exchange.
+    fn sync<'a>(&'a self, key: &'a str, limit: Limit, used: usize) -> BoxFuture<'a, io::Result<()>>;
+}
+
+/// Continuously refilled token bucket, `now` is any monotonic clock.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Bucket {
+    pub(crate) tokens: f64,
+    pub(crate) updated: Duration,
+}
+
+impl Bucket {
+    pub(crate) fn new(limit: Limit, now: Duration) -> Self {
+        Self { tokens: limit.cap as f64, updated: now }
+    }
+
+    pub(crate) fn take(&mut self, limit: Limit, n: u32, now: Duration) -> Result<(), Duration> {
+        self.refill(limit, now);
+
+        // Bucket never holds more than `cap` tokens,
+        // so bigger requests would wait forever.
+        let n = f64::min(n as f64, limit.cap as f64);
+
+        // Tolerate float rounding of refilled tokens,
+        // otherwise caller would wake up just before the refill.
+        if self.tokens + 1e-9 >= n {
+            self.tokens = f64::max(self.tokens - n, 0.0);
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((n - self.tokens) / Self::rate(limit)))
+        }
+    }
+
+    pub(crate) fn sync(&mut self, limit: Limit, used: usize, now: Duration) {
+        self.refill(limit, now);
+
+        self.tokens = f64::min(self.tokens, f64::max(limit.cap as f64 - used as f64, 0.0));
+    }
+
+    fn refill(&mut self, limit: Limit, now: Duration) {
+        let elapsed = now.saturating_sub(self.updated).as_secs_f64();
+
+        self.tokens = f64::min(self.tokens + elapsed * Self::rate(limit), limit.cap as f64);
+        self.updated = self.updated.max(now);
+    }
+
+    // Permits per second.
+    fn rate(limit: Limit) -> f64 {
+        limit.cap as f64 / limit.interval.as_secs_f64()
+    }
+}
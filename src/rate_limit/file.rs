@@ -0,0 +1,121 @@
+use super::{Bucket, Limit, RateLimitBackend};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Budget shared by processes of the same host through a locked file.
+///
+/// File holds one `key tokens updated` line per limit,
+/// where `updated` is a UNIX timestamp in nanoseconds.
+pub struct FileBackend {
+    path: PathBuf,
+}
+
+impl FileBackend {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self { path: path.as_ref().to_owned() }
+    }
+
+    fn with_bucket<T>(
+        path: &Path,
+        key: &str,
+        limit: Limit,
+        f: impl FnOnce(&mut Bucket, Duration) -> T,
+    ) -> io::Result<T> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        // Released when file is closed.
+        file.lock()?;
+
+        let mut buckets = read(&mut file)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let result = f(
+            buckets.entry(key.to_owned()).or_insert_with(|| Bucket::new(limit, now)),
+            now,
+        );
+        write(&mut file, &buckets)?;
+
+        Ok(result)
+    }
+}
+
+fn read(file: &mut File) -> io::Result<HashMap<String, Bucket>> {
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+
+    Ok(HashMap::from_iter(
+        content.lines().filter_map(|line| {
+            let mut parts = line.split(' ');
+            let key = parts.next()?;
+            let tokens = parts.next()?.parse().ok()?;
+            let updated = Duration::from_nanos(parts.next()?.parse().ok()?);
+
+            Some((key.to_owned(), Bucket { tokens, updated }))
+        })
+    ))
+}
+
+fn write(file: &mut File, buckets: &HashMap<String, Bucket>) -> io::Result<()> {
+    let content = buckets.iter()
+        .map(|(key, b)| format!("{key} {} {}\n", b.tokens, b.updated.as_nanos()))
+        .collect::<String>();
+
+    file.set_len(0)?;
+    file.rewind()?;
+    file.write_all(content.as_bytes())
+}
+
+impl RateLimitBackend for FileBackend {
+    fn try_acquire<'a>(
+        &'a self,
+        key: &'a str,
+        limit: Limit,
+        n: u32,
+    ) -> BoxFuture<'a, io::Result<Result<(), Duration>>> {
+        let path = self.path.clone();
+        let key = key.to_owned();
+
+        tokio::task::spawn_blocking(move || {
+            Self::with_bucket(&path, &key, limit, |b, now| b.take(limit, n, now))
+        }).map(Result::unwrap).boxed()
+    }
+
+    fn sync<'a>(&'a self, key: &'a str, limit: Limit, used: usize) -> BoxFuture<'a, io::Result<()>> {
+        let path = self.path.clone();
+        let key = key.to_owned();
+
+        tokio::task::spawn_blocking(move || {
+            Self::with_bucket(&path, &key, limit, |b, now| b.sync(limit, used, now))
+        }).map(Result::unwrap).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn file_backend() {
+        let path = std::env::temp_dir().join(format!("market-view-{}.limits", std::process::id()));
+        let limit = Limit { cap: 6000, interval: Duration::from_secs(60) };
+        // Two handles behave like two processes.
+        let backend1 = FileBackend::new(&path);
+        let backend2 = FileBackend::new(&path);
+
+        backend1.try_acquire("weight", limit, 5000).await.unwrap().unwrap();
+        backend2.sync("weight", limit, 5500).await.unwrap();
+        let wait = backend2.try_acquire("weight", limit, 1000).await.unwrap().unwrap_err();
+        assert!(wait > Duration::from_secs(4) && wait <= Duration::from_secs(5));
+        backend1.try_acquire("weight", limit, 500).await.unwrap().unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
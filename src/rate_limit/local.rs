@@ -0,0 +1,61 @@
+use super::{Bucket, Limit, RateLimitBackend};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Budget of a single process.
+pub struct LocalBackend {
+    start: Instant,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl LocalBackend {
+    pub fn new() -> Self {
+        Self { start: Instant::now(), buckets: Mutex::new(HashMap::new()) }
+    }
+
+    pub(crate) fn take(&self, key: &str, limit: Limit, n: u32) -> Result<(), Duration> {
+        let now = self.start.elapsed();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        buckets.entry(key.to_owned())
+            .or_insert_with(|| Bucket::new(limit, now))
+            .take(limit, n, now)
+    }
+
+    pub(crate) fn sync_used(&self, key: &str, limit: Limit, used: usize) {
+        let now = self.start.elapsed();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        buckets.entry(key.to_owned())
+            .or_insert_with(|| Bucket::new(limit, now))
+            .sync(limit, used, now);
+    }
+}
+
+impl Default for LocalBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateLimitBackend for LocalBackend {
+    fn try_acquire<'a>(
+        &'a self,
+        key: &'a str,
+        limit: Limit,
+        n: u32,
+    ) -> BoxFuture<'a, io::Result<Result<(), Duration>>> {
+        futures::future::ready(Ok(self.take(key, limit, n))).boxed()
+    }
+
+    fn sync<'a>(&'a self, key: &'a str, limit: Limit, used: usize) -> BoxFuture<'a, io::Result<()>> {
+        self.sync_used(key, limit, used);
+
+        futures::future::ready(Ok(())).boxed()
+    }
+}
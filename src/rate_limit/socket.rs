@@ -0,0 +1,181 @@
+use super::{Limit, LocalBackend, RateLimitBackend};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Mutex;
+
+// Line based protocol, every request gets a single line response:
+//
+// acquire <key> <cap> <interval nanos> <n>    ->  ok | wait <nanos>
+// sync <key> <cap> <interval nanos> <used>    ->  ok
+
+/// Budget shared by processes of the same host through a coordinator,
+/// see [`serve_coordinator`].
+pub struct SocketBackend {
+    path: PathBuf,
+    conn: Mutex<Option<BufReader<UnixStream>>>,
+}
+
+impl SocketBackend {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self { path: path.as_ref().to_owned(), conn: Mutex::new(None) }
+    }
+
+    async fn request(&self, request: String) -> io::Result<String> {
+        let mut conn = self.conn.lock().await;
+
+        if conn.is_none() {
+            *conn = Some(BufReader::new(UnixStream::connect(&self.path).await?));
+        }
+
+        let result = async {
+            let stream = conn.as_mut().unwrap();
+            stream.get_mut().write_all(request.as_bytes()).await?;
+
+            let mut response = String::new();
+            if stream.read_line(&mut response).await? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+            Ok(response)
+        }.await;
+
+        if result.is_err() {
+            // Reconnect next time.
+            *conn = None;
+        }
+
+        result
+    }
+}
+
+impl RateLimitBackend for SocketBackend {
+    fn try_acquire<'a>(
+        &'a self,
+        key: &'a str,
+        limit: Limit,
+        n: u32,
+    ) -> BoxFuture<'a, io::Result<Result<(), Duration>>> {
+        async move {
+            let request = format!("acquire {key} {} {} {n}\n", limit.cap, limit.interval.as_nanos());
+            let response = self.request(request).await?;
+
+            match response.trim_end().split_once(' ') {
+                None if response.trim_end() == "ok" => Ok(Ok(())),
+                Some(("wait", nanos)) => match nanos.parse() {
+                    Ok(nanos) => Ok(Err(Duration::from_nanos(nanos))),
+                    Err(_) => Err(io::Error::from(io::ErrorKind::InvalidData)),
+                },
+                _ => Err(io::Error::from(io::ErrorKind::InvalidData)),
+            }
+        }.boxed()
+    }
+
+    fn sync<'a>(&'a self, key: &'a str, limit: Limit, used: usize) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            let request = format!("sync {key} {} {} {used}\n", limit.cap, limit.interval.as_nanos());
+
+            match self.request(request).await?.trim_end() {
+                "ok" => Ok(()),
+                _ => Err(io::Error::from(io::ErrorKind::InvalidData)),
+            }
+        }.boxed()
+    }
+}
+
+/// Serves budgets to [`SocketBackend`]s on a Unix socket at `path`.
+///
+/// Budgets live in memory of the coordinator,
+/// so it should be run by a single long-living process of the host.
+/// Socket left behind by a crashed coordinator is replaced.
+pub async fn serve_coordinator(path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+    // Nobody listens on a stale socket, while a running coordinator keeps its socket.
+    if UnixStream::connect(path).await.is_err_and(|err| err.kind() == io::ErrorKind::ConnectionRefused) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    let backend = Arc::new(LocalBackend::new());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(serve_client(stream, Arc::clone(&backend)));
+    }
+}
+
+async fn serve_client(stream: UnixStream, backend: Arc<LocalBackend>) {
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        match stream.read_line(&mut line).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+
+        let response = match handle(&backend, &line) {
+            Some(response) => response,
+            None => String::from("error\n"),
+        };
+        if stream.get_mut().write_all(response.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn handle(backend: &LocalBackend, line: &str) -> Option<String> {
+    let mut parts = line.split_whitespace();
+    let command = parts.next()?;
+    let key = parts.next()?;
+    let limit = Limit {
+        cap: parts.next()?.parse().ok()?,
+        interval: Duration::from_nanos(parts.next()?.parse().ok()?),
+    };
+
+    match command {
+        "acquire" => match backend.take(key, limit, parts.next()?.parse().ok()?) {
+            Ok(()) => Some(String::from("ok\n")),
+            Err(wait) => Some(format!("wait {}\n", wait.as_nanos())),
+        },
+        "sync" => {
+            backend.sync_used(key, limit, parts.next()?.parse().ok()?);
+            Some(String::from("ok\n"))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[tokio::test]
+    async fn coordinator() {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        let dir = std::env::temp_dir().join(format!("market-view-{}-{nanos}", std::process::id()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("coordinator.sock");
+        let limit = Limit { cap: 100, interval: Duration::from_secs(10) };
+
+        // Crashed coordinator left its socket behind.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let server = tokio::spawn(serve_coordinator(path.clone()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let backend1 = SocketBackend::new(&path);
+        let backend2 = SocketBackend::new(&path);
+
+        backend1.try_acquire("weight", limit, 60).await.unwrap().unwrap();
+        backend2.try_acquire("weight", limit, 40).await.unwrap().unwrap();
+        assert!(backend1.try_acquire("weight", limit, 10).await.unwrap().is_err());
+        backend2.sync("weight", limit, 100).await.unwrap();
+
+        server.abort();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
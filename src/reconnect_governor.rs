@@ -0,0 +1,124 @@
+use crate::rate_limit::RateLimitBackend;
+use crate::TokenBucket;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Paces websocket connection attempts of a Place, so connections dropped together
+/// by a network blip come back one by one instead of all at once,
+/// spreading their resyncs as well.
+pub(crate) struct ReconnectGovernor {
+    attempts: TokenBucket,
+    stagger: Duration,
+    // Earliest time of the next attempt.
+    next_slot: Mutex<Instant>,
+}
+
+impl ReconnectGovernor {
+    /// Allows `limit` attempts per `interval`, at least `stagger` apart.
+    pub(crate) fn new(
+        key: String,
+        limit: usize,
+        interval: Duration,
+        stagger: Duration,
+        backend: Arc<dyn RateLimitBackend>,
+    ) -> Self {
+        Self {
+            attempts: TokenBucket::new(key, limit, interval, backend),
+            stagger,
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits for the turn to connect.
+    pub(crate) async fn acquire(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.stagger;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+
+        self.attempts.acquire(1).await;
+    }
+}
+
+/// Random number within `[0, 1)`.
+fn jitter() -> f64 {
+    // Every `RandomState` has new keys, which is random enough to spread reconnects.
+    (RandomState::new().build_hasher().finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Exponential backoff of reconnects of a single connection.
+pub(crate) struct Backoff {
+    base: Duration,
+    max: Duration,
+    failures: u32,
+}
+
+impl Backoff {
+    pub(crate) fn new(base: Duration, max: Duration) -> Self {
+        Self { base, max, failures: 0 }
+    }
+
+    /// Delay before the next attempt, doubled by every consecutive failure up to `max`
+    /// and randomized between its half and full value.
+    pub(crate) fn next(&mut self) -> Duration {
+        let delay = self.base.saturating_mul(2u32.saturating_pow(self.failures)).min(self.max);
+        self.failures = self.failures.saturating_add(1);
+
+        delay / 2 + delay.mul_f64(jitter()) / 2
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::LocalBackend;
+
+    #[test]
+    fn backoff() {
+        let secs = Duration::from_secs;
+        let mut backoff = Backoff::new(secs(1), secs(10));
+
+        for max in [1, 2, 4, 8, 10, 10] {
+            let delay = backoff.next();
+            assert!(secs(max) / 2 <= delay && delay <= secs(max), "{delay:?} isn't within {max}s");
+        }
+        backoff.reset();
+        assert!(backoff.next() <= secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn governor() {
+        let governor = Arc::new(ReconnectGovernor::new(
+            String::from("test-connections"), 3, Duration::from_secs(60),
+            Duration::from_millis(100), Arc::new(LocalBackend::new()),
+        ));
+        let start = Instant::now();
+
+        // Five connections reconnect at once.
+        let attempts = Vec::from_iter((0..5).map(|_| {
+            let governor = Arc::clone(&governor);
+            tokio::spawn(async move {
+                governor.acquire().await;
+                start.elapsed().as_millis()
+            })
+        }));
+        let mut times = Vec::new();
+        for attempt in attempts {
+            times.push(attempt.await.unwrap());
+        }
+        times.sort();
+
+        // Staggered until the limit is used up, then wait for its refill.
+        assert_eq!(times, [0, 100, 200, 20_000, 40_000]);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{Bbo, Book};
+use arc_swap::{ArcSwap, ArcSwapOption};
+use std::sync::Arc;
+
+/// Latest published version of a [`Book`], along with the best bid and offer
+/// from the book ticker stream if it's enabled, see [`SharedBook::bbo`].
+///
+/// Readers get consistent immutable snapshots and never block the writer.
+#[derive(Debug, Clone)]
+pub struct SharedBook(Arc<Shared>);
+
+#[derive(Debug)]
+struct Shared {
+    book: ArcSwap<Book>,
+    ticker: ArcSwapOption<Bbo>,
+}
+
+type Update = Box<dyn Fn(&mut Book) + Send + Sync>;
+
+/// The only writer of a [`SharedBook`].
+///
+/// Keeps two copies of the book: the published one and the spare one,
+/// which lags behind by the last update. Update is applied to the spare copy,
+/// which is then published, and the previously published copy becomes spare.
+/// Copy is cloned only if readers still hold it by the time of the next update.
+pub(crate) struct BookWriter {
+    shared: SharedBook,
+    spare: Arc<Book>,
+    // Update which the spare copy misses.
+    pending: Option<Update>,
+}
+
+impl SharedBook {
+    pub(crate) fn new(book: Book) -> Self {
+        Self(Arc::new(Shared { book: ArcSwap::from_pointee(book), ticker: ArcSwapOption::empty() }))
+    }
+
+    pub fn load(&self) -> Arc<Book> {
+        self.0.book.load_full()
+    }
+
+    /// Best bid and offer of the book ticker if it's ahead of the book,
+    /// otherwise the best levels of the book.
+    ///
+    /// Ticker updates arrive in real time, while the book gets batches of updates
+    /// every update interval, so the ticker is usually newer.
+    pub fn bbo(&self) -> Option<Bbo> {
+        let book = self.load();
+
+        match self.0.ticker.load().as_deref() {
+            Some(&ticker) if ticker.update_id > book.update_id() => Some(ticker),
+            _ => book.bbo(),
+        }
+    }
+
+    /// Keeps the ticker update unless a newer one is already there,
+    /// as redundant connections deliver it more than once.
+    pub(crate) fn set_ticker(&self, ticker: Bbo) {
+        self.0.ticker.rcu(|current| match current {
+            Some(current) if current.update_id >= ticker.update_id => Some(Arc::clone(current)),
+            _ => Some(Arc::new(ticker)),
+        });
+    }
+
+    pub(crate) fn writer(&self) -> BookWriter {
+        BookWriter {
+            shared: self.clone(),
+            spare: Arc::new(Book::clone(&self.0.book.load())),
+            pending: None,
+        }
+    }
+}
+
+impl BookWriter {
+    pub(crate) fn book(&self) -> Arc<Book> {
+        self.shared.load()
+    }
+
+    /// Applies `update` and publishes the result,
+    /// `update` must give the same result when applied to both copies.
+    pub(crate) fn update(&mut self, update: impl Fn(&mut Book) + Send + Sync + 'static) {
+        if let Some(book) = Arc::get_mut(&mut self.spare) {
+            if let Some(pending) = self.pending.take() {
+                pending(book);
+            }
+        } else {
+            // Readers still hold the spare copy.
+            self.spare = self.shared.load();
+            self.pending = None;
+        }
+
+        update(Arc::make_mut(&mut self.spare));
+
+        let published = self.shared.0.book.swap(Arc::clone(&self.spare));
+        self.spare = published;
+        self.pending = Some(Box::new(update));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::order;
+    use crate::{Order, SideKind};
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn shared_book() {
+        let shared = SharedBook::new(Book::new(10, SideKind::Vec));
+        let mut writer = shared.writer();
+
+        let mut orders = Vec::new();
+        for i in 1..=5 {
+            let order = Order { price: Decimal::from(i), size: dec!(1) };
+            orders.push(order);
+            writer.update(move |book| book.asks.diff_update(order));
+
+            if i == 3 {
+                // Reader holds a version while writer keeps going.
+                let held = shared.load();
+                writer.update(|_| {});
+                assert_eq!(held.asks().to_vec(), orders);
+            }
+        }
+
+        assert_eq!(shared.load().asks().to_vec(), orders);
+        writer.update(|_| {});
+        assert_eq!(shared.load().asks().to_vec(), orders);
+    }
+
+    #[test]
+    fn bbo() {
+        let shared = SharedBook::new(Book::new(10, SideKind::Vec));
+        let mut writer = shared.writer();
+        assert_eq!(shared.bbo(), None);
+
+        writer.update(|book| {
+            book.update_id = 10;
+            book.bids.diff_update(Order { price: dec!(1), size: dec!(1) });
+            book.asks.diff_update(Order { price: dec!(2), size: dec!(1) });
+        });
+        let from_book = Bbo { update_id: 10, bid: order(dec!(1), dec!(1)), ask: order(dec!(2), dec!(1)) };
+        assert_eq!(shared.bbo(), Some(from_book));
+
+        // Ticker is ahead of the book.
+        let ticker = Bbo { update_id: 12, bid: order(dec!(1.5), dec!(3)), ask: order(dec!(2), dec!(1)) };
+        shared.set_ticker(ticker);
+        shared.set_ticker(Bbo { update_id: 11, ..from_book });
+        assert_eq!(shared.bbo(), Some(ticker));
+
+        // Book catches up.
+        writer.update(|book| {
+            book.update_id = 12;
+            book.bids.diff_update(Order { price: dec!(1.5), size: dec!(3) });
+        });
+        assert_eq!(shared.bbo(), Some(Bbo { update_id: 12, ..ticker }));
+    }
+}
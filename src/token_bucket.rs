@@ -1,43 +1,122 @@
+use crate::rate_limit::{Limit, RateLimitBackend};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::Semaphore;
+use tokio::sync::Mutex;
 
+/// Rate limiter which allows bursts of `cap` permits
+/// and continuously refills `cap` permits per `interval`.
 pub(crate) struct TokenBucket {
-    sem: Arc<Semaphore>,
-    jh: tokio::task::JoinHandle<()>,
+    key: String,
+    limit: Limit,
+    backend: Arc<dyn RateLimitBackend>,
+    // Tokio's mutex is fair, so waiting acquirers are served in FIFO order.
+    queue: Mutex<()>,
 }
 
 impl TokenBucket {
-    pub(crate) fn new(cap: usize, rate: Duration) -> Self {
-        let sem = Arc::new(Semaphore::new(cap));
-
-        let jh = tokio::spawn({
-            let sem = Arc::clone(&sem);
-            let mut interval = tokio::time::interval(rate);
-            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    pub(crate) fn new(
+        key: String,
+        cap: usize,
+        interval: Duration,
+        backend: Arc<dyn RateLimitBackend>,
+    ) -> Self {
+        Self { key, limit: Limit { cap, interval }, backend, queue: Mutex::new(()) }
+    }
 
-            async move {
-                loop {
-                    interval.tick().await;
+    pub(crate) async fn acquire(&self, n: u32) {
+        let _queue = self.queue.lock().await;
 
-                    if sem.available_permits() < cap {
-                        sem.add_permits(1);
-                    }
+        loop {
+            match self.backend.try_acquire(&self.key, self.limit, n).await {
+                Ok(Ok(())) => return,
+                Ok(Err(wait)) => tokio::time::sleep(wait).await,
+                Err(err) => {
+                    log_event!(error, "rate limit", { key = self.key, ?err }, "{} backend failed: {err:?}", self.key);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
                 }
             }
-        });
-
-        Self { sem, jh }
+        }
     }
 
-    pub(crate) async fn acquire(&self, n: u32) {
-        let permit = self.sem.acquire_many(n).await.unwrap();
-        permit.forget();
+    /// Reconciles bucket with amount of permits `used` within the current interval
+    /// as reported by the other side, which may be shared with other clients.
+    pub(crate) async fn sync(&self, used: usize) {
+        if let Err(err) = self.backend.sync(&self.key, self.limit, used).await {
+            log_event!(error, "rate limit", { key = self.key, ?err }, "{} backend failed: {err:?}", self.key);
+        }
     }
 }
 
-impl Drop for TokenBucket {
-    fn drop(&mut self) {
-        self.jh.abort();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::LocalBackend;
+    use tokio::time::Instant;
+
+    fn local(cap: usize, interval: Duration) -> TokenBucket {
+        TokenBucket::new(String::from("test"), cap, interval, Arc::new(LocalBackend::new()))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn burst() {
+        let tb = local(10, Duration::from_secs(1));
+        let start = Instant::now();
+
+        tb.acquire(4).await;
+        tb.acquire(6).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        tb.acquire(1).await;
+        assert_eq!(start.elapsed().as_millis(), 100);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refill_rate() {
+        // REQUEST_WEIGHT limit of Binance spot.
+        let tb = local(6000, Duration::from_secs(60));
+        let start = Instant::now();
+
+        tb.acquire(6000).await;
+        for _ in 0..10 {
+            tb.acquire(250).await;
+        }
+        // 2500 weight at 100 weight per second.
+        assert_eq!(start.elapsed().as_secs(), 25);
+
+        tokio::time::sleep(Duration::from_secs(120)).await;
+        let start = Instant::now();
+
+        // Bucket doesn't accumulate more than its capacity.
+        tb.acquire(6000).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        tb.acquire(100).await;
+        assert_eq!(start.elapsed().as_secs(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sync() {
+        let tb = local(100, Duration::from_secs(10));
+        let start = Instant::now();
+
+        tb.acquire(10).await;
+        // Somebody else used the rest.
+        tb.sync(100).await;
+        tb.acquire(20).await;
+        assert_eq!(start.elapsed().as_secs(), 2);
+
+        // Reported usage lower than ours doesn't add permits.
+        tb.sync(0).await;
+        tb.acquire(100).await;
+        assert_eq!(start.elapsed().as_secs(), 12);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn oversized() {
+        let tb = local(5, Duration::from_secs(1));
+        let start = Instant::now();
+
+        tb.acquire(5).await;
+        tb.acquire(250).await;
+        assert_eq!(start.elapsed().as_secs(), 1);
     }
 }
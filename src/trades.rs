@@ -0,0 +1,124 @@
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::broadcast;
+
+/// Trades kept for subscribers which fall behind, older ones are reported as lagged.
+const SUBSCRIBER_BUFFER: usize = 1_024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggressor {
+    Buyer,
+    Seller,
+}
+
+/// Trade or aggregated trade, depending on the stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trade {
+    /// Consecutive within the pair and the stream.
+    pub id: u64,
+    pub price: Decimal,
+    pub size: Decimal,
+    /// Side which took liquidity.
+    pub aggressor: Aggressor,
+    pub time: SystemTime,
+}
+
+#[derive(Debug)]
+struct Ring {
+    trades: VecDeque<Trade>,
+    cap: usize,
+    // Zero until the first trade.
+    last_id: u64,
+    missed: u64,
+}
+
+#[derive(Debug)]
+struct TradesCell {
+    ring: Mutex<Ring>,
+    tx: broadcast::Sender<Trade>,
+}
+
+/// Recent trades of a pair streamed along with its book by [`Config::trades`](crate::Config::trades),
+/// see [`PlaceHandle::trades`](crate::PlaceHandle::trades).
+#[derive(Debug, Clone)]
+pub struct Trades(Arc<TradesCell>);
+
+impl Trades {
+    /// Trades keeping up to `cap` recent ones.
+    pub(crate) fn new(cap: usize) -> Self {
+        let ring = Ring { trades: VecDeque::new(), cap, last_id: 0, missed: 0 };
+
+        Self(Arc::new(TradesCell { ring: Mutex::new(ring), tx: broadcast::channel(SUBSCRIBER_BUFFER).0 }))
+    }
+
+    /// Recent trades from oldest to newest.
+    pub fn recent(&self) -> Vec<Trade> {
+        Vec::from(self.0.ring.lock().unwrap().trades.clone())
+    }
+
+    /// Receives every following trade.
+    pub fn subscribe(&self) -> broadcast::Receiver<Trade> {
+        self.0.tx.subscribe()
+    }
+
+    /// Number of trades which were never received, judging by gaps between ids.
+    pub fn missed(&self) -> u64 {
+        self.0.ring.lock().unwrap().missed
+    }
+
+    /// Keeps the trade unless it's already there, as overlapping connections deliver it twice.
+    /// Returns number of trades missed right before it.
+    pub(crate) fn push(&self, trade: Trade) -> u64 {
+        let mut ring = self.0.ring.lock().unwrap();
+
+        if trade.id <= ring.last_id {
+            return 0;
+        }
+        let missed = if ring.last_id > 0 { trade.id - ring.last_id - 1 } else { 0 };
+        ring.missed += missed;
+        ring.last_id = trade.id;
+
+        if ring.cap > 0 {
+            if ring.trades.len() >= ring.cap {
+                ring.trades.pop_front();
+            }
+            ring.trades.push_back(trade);
+        }
+        // It's fine to have no subscribers.
+        let _ = self.0.tx.send(trade);
+
+        missed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trades() {
+        let trades = Trades::new(3);
+        let subscribed = trades.clone();
+        let mut rx = subscribed.subscribe();
+        let trade = |id| Trade {
+            id, price: Decimal::ONE, size: Decimal::from(id), aggressor: Aggressor::Buyer, time: SystemTime::UNIX_EPOCH,
+        };
+
+        assert_eq!(trades.push(trade(1)), 0);
+        assert_eq!(trades.push(trade(2)), 0);
+        // Delivered by both connections during rotation.
+        assert_eq!(trades.push(trade(2)), 0);
+        assert_eq!(trades.push(trade(5)), 2);
+        assert_eq!(trades.push(trade(6)), 0);
+
+        // Every handle sees the same trades.
+        assert_eq!(subscribed.recent(), [trade(2), trade(5), trade(6)]);
+        assert_eq!(subscribed.missed(), 2);
+        for id in [1, 2, 5, 6] {
+            assert_eq!(rx.try_recv(), Ok(trade(id)));
+        }
+        assert!(rx.try_recv().is_err());
+    }
+}